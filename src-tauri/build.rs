@@ -1,7 +1,10 @@
 use vergen::{vergen, Config};
 
 fn main() {
+    #[cfg(feature = "gui")]
     tauri_build::build();
     vergen(Config::default()).unwrap();
-    println!("cargo:rustc-link-lib=framework=ServiceManagement");
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("macos") {
+        println!("cargo:rustc-link-lib=framework=ServiceManagement");
+    }
 }
@@ -0,0 +1,224 @@
+//! Headless command line interface.
+#![allow(clippy::module_name_repetitions)]
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::Arc;
+
+use clap::{ArgAction, Parser, Subcommand};
+use eyre::{bail, Result, WrapErr};
+use tracing::{debug, error, info, Level};
+
+use tmexclude_lib::{
+    walk_recursive, watch, Config, ConfigManager, Diagnostic, ExcludeState, ExclusionActionBatch,
+    Metrics, PreConfig, RuleVerdict,
+};
+
+/// Exclude undesired files (node_modules, target, etc) from your backups.
+#[derive(Debug, Parser)]
+#[command(name = "tmexclude-cli", version)]
+struct Args {
+    /// Path to config file. Defaults to `~/.config/tmexclude.yaml`, which is created if missing.
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+    /// Print more logs. May be specified multiple times.
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the config.
+    ReadConfig,
+    /// Check whether the config is valid.
+    CheckConfig,
+    /// Scan all directories and print actions to be applied, without applying them.
+    Scan,
+    /// Scan all directories and apply actions.
+    Apply,
+    /// Watch all directories and apply actions on changes.
+    Watch,
+    /// Explain why a path is or isn't excluded and what would be done to it.
+    Explain {
+        /// The path to explain.
+        path: PathBuf,
+    },
+}
+
+fn config_manager(path: Option<PathBuf>) -> Result<ConfigManager> {
+    Ok(match path {
+        Some(path) => ConfigManager::with_path(path),
+        None => ConfigManager::new()?,
+    })
+}
+
+fn load_pre_config(manager: &ConfigManager) -> Result<PreConfig> {
+    manager
+        .load()
+        .wrap_err_with(|| format!("Failed to load config {}", manager.path().display()))
+}
+
+fn load_config(manager: &ConfigManager) -> Result<Config> {
+    Config::try_from(load_pre_config(manager)?).wrap_err("Invalid config")
+}
+
+fn scan(config: &Config) -> ExclusionActionBatch {
+    let mut batch = walk_recursive(
+        (*config.walk).clone(),
+        config.backend.clone(),
+        |path| debug!("Scanning {}", path.display()),
+        Arc::new(AtomicUsize::new(0)),
+        Arc::new(AtomicBool::new(false)),
+    );
+    if config.no_include {
+        batch.remove.clear();
+    }
+    batch.add.sort();
+    batch.remove.sort();
+    batch
+}
+
+fn print_batch(batch: &ExclusionActionBatch) {
+    for path in &batch.add {
+        match batch.rules.get(path) {
+            Some(rule) => println!("+ {} ({rule})", path.display()),
+            None => println!("+ {}", path.display()),
+        }
+    }
+    for path in &batch.remove {
+        println!("- {}", path.display());
+    }
+}
+
+fn apply(config: &Config) -> Result<()> {
+    let batch = scan(config);
+    print_batch(&batch);
+    let count = batch.count();
+    if let Err(errors) = batch.apply(&*config.backend) {
+        for (path, e) in &errors {
+            error!("Error when applying on file {}: {}", path.display(), e);
+        }
+        bail!("Failed to apply {} of {} actions", errors.len(), count);
+    }
+    info!("Applied {} actions", count);
+    Ok(())
+}
+
+fn explain(config: &Config, path: &Path) -> Result<()> {
+    let path = path
+        .canonicalize()
+        .wrap_err_with(|| format!("Invalid path {}", path.display()))?;
+    let explanation = tmexclude_lib::explain(&path, &config.walk, &*config.backend);
+    println!("{}", path.display());
+    match explanation.state {
+        Some(state) => println!("  current state: {state:?}"),
+        None => println!("  current state: unknown"),
+    }
+    // Paths not covered, skipped or under excluded ancestors are never visited by the walker.
+    let visited = !explanation.directories.is_empty()
+        && explanation.skipped_by.is_none()
+        && explanation.excluded_ancestor.is_none();
+    let action = match (explanation.state, explanation.excluded_by.is_some()) {
+        _ if !visited => "none",
+        (Some(ExcludeState::Included | ExcludeState::Inconsistent), true) => "exclude",
+        (Some(ExcludeState::Excluded | ExcludeState::Inconsistent), false)
+            if !config.no_include =>
+        {
+            "include"
+        }
+        _ => "none",
+    };
+    println!("  pending action: {action}");
+    if explanation.directories.is_empty() {
+        println!("  not covered by any directory");
+        return Ok(());
+    }
+    for directory in &explanation.directories {
+        println!("  covered by: {}", directory.display());
+    }
+    if let Some(skip) = &explanation.skipped_by {
+        println!("  skipped by: {}", skip.display());
+    }
+    if let Some(ancestor) = &explanation.excluded_ancestor {
+        println!("  ancestor already excluded: {}", ancestor.display());
+    }
+    if explanation.protected {
+        println!("  protected");
+    }
+    if explanation.gitignored {
+        println!("  ignored by git");
+    }
+    if let Some(rule) = &explanation.excluded_by {
+        println!("  excluded by: {rule}");
+    }
+    for rule in &explanation.rules {
+        println!(
+            "  {} {} (in {}): {}",
+            if rule.verdict == RuleVerdict::Matched {
+                "+"
+            } else {
+                " "
+            },
+            rule.rule,
+            rule.directory.display(),
+            match rule.verdict {
+                RuleVerdict::Matched => "matched",
+                RuleVerdict::NotMatched => "not matched",
+                RuleVerdict::MissingSentinel => "missing sentinel",
+                RuleVerdict::ForbiddenSentinel => "forbidden sentinel exists",
+                RuleVerdict::ContentMismatch => "file content doesn't match",
+                RuleVerdict::TooSmall => "smaller than min-size",
+                RuleVerdict::TooNew => "modified within older-than",
+                RuleVerdict::TooOld => "not modified within newer-than",
+            }
+        );
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(match args.verbose {
+            0 => Level::WARN,
+            1 => Level::INFO,
+            2 => Level::DEBUG,
+            _ => Level::TRACE,
+        })
+        .init();
+
+    let manager = config_manager(args.config)?;
+    match args.command {
+        Command::ReadConfig => {
+            let pre_config = load_pre_config(&manager)?;
+            print!("{}", serde_yaml::to_string(&pre_config)?);
+        }
+        Command::CheckConfig => {
+            let diagnostics = manager
+                .validate()
+                .wrap_err_with(|| format!("Failed to load config {}", manager.path().display()))?;
+            for diagnostic in &diagnostics {
+                match diagnostic.location {
+                    Some(_) => println!("{}:{diagnostic}", manager.path().display()),
+                    None => println!("{}: {diagnostic}", manager.path().display()),
+                }
+            }
+            if diagnostics.iter().any(Diagnostic::is_error) {
+                bail!("Config is invalid");
+            }
+            println!("Config is valid.");
+        }
+        Command::Scan => print_batch(&scan(&load_config(&manager)?)),
+        Command::Apply => apply(&load_config(&manager)?)?,
+        Command::Watch => {
+            let config = Arc::new(load_config(&manager)?);
+            info!("Watching for changes");
+            futures::executor::block_on(watch(config, Arc::new(Metrics::default())))?;
+        }
+        Command::Explain { path } => explain(&load_config(&manager)?, &path)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+//! Age conditions of rules, and re-evaluation of paths when they cross an age threshold.
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Units accepted in ages, largest first.
+const UNITS: [(char, u64); 5] = [
+    ('w', 7 * 24 * 60 * 60),
+    ('d', 24 * 60 * 60),
+    ('h', 60 * 60),
+    ('m', 60),
+    ('s', 1),
+];
+
+/// An age threshold like `30d` or `1d12h`.
+///
+/// Accepted units are `w`, `d`, `h`, `m` and `s`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Age(Duration);
+
+impl Age {
+    /// The age as a duration.
+    #[must_use]
+    pub const fn duration(self) -> Duration {
+        self.0
+    }
+}
+
+impl FromStr for Age {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid age `{s}`, expect something like `30d` or `1d12h`");
+        let mut secs = 0u64;
+        let mut rest = s.trim();
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+        while !rest.is_empty() {
+            let split = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .ok_or_else(invalid)?;
+            let (value, tail) = rest.split_at(split);
+            let mut chars = tail.chars();
+            let unit = chars.next().ok_or_else(invalid)?;
+            let (_, scale) = UNITS
+                .iter()
+                .find(|(name, _)| *name == unit)
+                .ok_or_else(invalid)?;
+            let value: u64 = value.parse().map_err(|_| invalid())?;
+            secs = value
+                .checked_mul(*scale)
+                .and_then(|value| secs.checked_add(value))
+                .ok_or_else(invalid)?;
+            rest = chars.as_str();
+        }
+        Ok(Self(Duration::from_secs(secs)))
+    }
+}
+
+impl Display for Age {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let secs = self.0.as_secs();
+        let (unit, scale) = UNITS
+            .iter()
+            .find(|(_, scale)| secs % scale == 0)
+            .expect("every age is a multiple of seconds");
+        write!(f, "{}{unit}", secs / scale)
+    }
+}
+
+impl Serialize for Age {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Age {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Merge re-evaluation times of paths into `schedule`.
+///
+/// Only the earliest time is kept if a path is scheduled multiple times.
+pub fn merge(
+    schedule: &mut HashMap<PathBuf, SystemTime>,
+    entries: impl IntoIterator<Item = (PathBuf, SystemTime)>,
+) {
+    for (path, at) in entries {
+        schedule
+            .entry(path)
+            .and_modify(|prev| *prev = (*prev).min(at))
+            .or_insert(at);
+    }
+}
+
+/// Paths to be re-evaluated when they cross an age threshold, and when.
+///
+/// Each watcher owns one, so that entries never outlive the config they are computed with.
+#[derive(Debug, Default)]
+pub struct Schedule(Mutex<HashMap<PathBuf, SystemTime>>);
+
+impl Schedule {
+    /// Schedule re-evaluation of paths at given times.
+    pub fn extend(&self, entries: impl IntoIterator<Item = (PathBuf, SystemTime)>) {
+        merge(&mut self.0.lock(), entries);
+    }
+    /// Take paths due for re-evaluation at `now`.
+    #[must_use]
+    pub fn take_due(&self, now: SystemTime) -> Vec<PathBuf> {
+        let mut schedule = self.0.lock();
+        let due: Vec<_> = schedule
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &due {
+            schedule.remove(path);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use tempfile::TempDir;
+
+    use crate::age::{Age, Schedule};
+
+    #[test]
+    fn must_parse_age() {
+        let age = |s: &str| s.parse::<Age>().map(Age::duration);
+        assert_eq!(age("30d"), Ok(Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(age("1d12h"), Ok(Duration::from_secs(36 * 60 * 60)));
+        assert_eq!(age("90s"), Ok(Duration::from_secs(90)));
+        assert!(age("").is_err());
+        assert!(age("30").is_err());
+        assert!(age("d").is_err());
+        assert!(age("30y").is_err());
+        assert!(age("-1d").is_err());
+    }
+
+    #[test]
+    fn must_display_age() {
+        for (input, expected) in [
+            ("30d", "30d"),
+            ("14d", "2w"),
+            ("1d12h", "36h"),
+            ("90s", "90s"),
+        ] {
+            assert_eq!(input.parse::<Age>().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn must_take_due() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = |name: &str| temp_dir.path().join(name);
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(60 * 60);
+
+        let schedule = Schedule::default();
+        schedule.extend([
+            (path("a"), now + hour),
+            (path("a"), now - hour),
+            (path("b"), now + hour),
+        ]);
+
+        assert_eq!(schedule.take_due(now), [path("a")]);
+        assert!(schedule.take_due(now).is_empty());
+        assert_eq!(schedule.take_due(now + hour), [path("b")]);
+    }
+}
@@ -0,0 +1,205 @@
+//! `CACHEDIR.TAG` backend.
+//!
+//! See <https://bford.info/cachedir/> for the specification.
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::backend::{ExcludeState, ExclusionBackend};
+use crate::error::ApplyError;
+
+const TAG_NAME: &str = "CACHEDIR.TAG";
+const SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+/// Line marking tags created by us, so that we never remove tags created by other tools.
+const MARKER: &str = "# This file is a cache directory tag created by tmexclude.";
+
+/// Excludes directories from backups by creating a `CACHEDIR.TAG` file in them.
+///
+/// Only directories can be tagged. Other paths are reported as [`ExcludeState::Unsupported`] and
+/// setting their state is a no-op, so no action is generated for files matching a rule.
+///
+/// Tags created by other tools (e.g. cargo) are respected but never removed. Directories tagged by
+/// them are reported as [`ExcludeState::Unsupported`] too, so that no action is generated for
+/// them either.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CachedirTag;
+
+enum Tag {
+    /// No tag, or a tag without a valid signature.
+    Missing,
+    /// A valid tag created by some other tool.
+    Foreign,
+    /// A valid tag created by us.
+    Owned,
+}
+
+fn read_tag(dir: &Path) -> io::Result<Tag> {
+    let mut content = vec![];
+    match fs::File::open(dir.join(TAG_NAME)) {
+        Ok(mut file) => file.read_to_end(&mut content)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Tag::Missing),
+        Err(e) => return Err(e),
+    };
+    Ok(if !content.starts_with(SIGNATURE) {
+        Tag::Missing
+    } else if content
+        .split(|c| *c == b'\n')
+        .any(|line| line == MARKER.as_bytes())
+    {
+        Tag::Owned
+    } else {
+        Tag::Foreign
+    })
+}
+
+fn is_dir(path: &Path) -> io::Result<bool> {
+    Ok(fs::symlink_metadata(path)?.is_dir())
+}
+
+impl ExclusionBackend for CachedirTag {
+    fn state(&self, path: &Path) -> io::Result<ExcludeState> {
+        if !is_dir(path)? {
+            return Ok(ExcludeState::Unsupported);
+        }
+        Ok(match read_tag(path)? {
+            Tag::Missing => ExcludeState::Included,
+            Tag::Foreign => ExcludeState::Unsupported,
+            Tag::Owned => ExcludeState::Excluded,
+        })
+    }
+
+    fn set_state(&self, path: &Path, excluded: bool) -> Result<(), ApplyError> {
+        if !is_dir(path)? {
+            return Ok(());
+        }
+        match (read_tag(path)?, excluded) {
+            (Tag::Foreign | Tag::Owned, true) | (Tag::Missing, false) => Ok(()),
+            (Tag::Missing, true) => {
+                // Fails if an invalid tag exists, because it's not ours to overwrite.
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(path.join(TAG_NAME))?;
+                file.write_all(SIGNATURE)?;
+                writeln!(file)?;
+                writeln!(file, "{}", MARKER)?;
+                writeln!(file, "# For information about cache directory tags, see:")?;
+                writeln!(file, "#\thttps://bford.info/cachedir/")?;
+                Ok(())
+            }
+            (Tag::Owned, false) => Ok(fs::remove_file(path.join(TAG_NAME))?),
+            (Tag::Foreign, false) => Err(ApplyError::ForeignCachedirTag),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use crate::backend::cachedir_tag::{CachedirTag, SIGNATURE, TAG_NAME};
+    use crate::backend::{Composite, ExcludeState, ExclusionBackend, Memory};
+    use crate::config::{Directory, WalkConfig};
+    use crate::error::ApplyError;
+    use crate::skip_cache::SkipCache;
+    use crate::walker::walk_non_recursive;
+
+    const FOREIGN_TAG: &[u8] =
+        b"Signature: 8a477f597d28d172789f06886806bc55\n# This file is created by cargo.\n";
+
+    #[test]
+    fn must_tag_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("target");
+        fs::create_dir(&dir).unwrap();
+
+        assert_eq!(CachedirTag.state(&dir).unwrap(), ExcludeState::Included);
+
+        CachedirTag.set_state(&dir, true).unwrap();
+        assert!(fs::read(dir.join(TAG_NAME)).unwrap().starts_with(SIGNATURE));
+        assert_eq!(CachedirTag.state(&dir).unwrap(), ExcludeState::Excluded);
+        // Idempotent.
+        CachedirTag.set_state(&dir, true).unwrap();
+
+        CachedirTag.set_state(&dir, false).unwrap();
+        assert!(!dir.join(TAG_NAME).exists());
+        assert_eq!(CachedirTag.state(&dir).unwrap(), ExcludeState::Included);
+    }
+
+    #[test]
+    fn must_not_remove_foreign_tag() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("target");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(TAG_NAME), FOREIGN_TAG).unwrap();
+
+        assert_eq!(CachedirTag.state(&dir).unwrap(), ExcludeState::Unsupported);
+        CachedirTag.set_state(&dir, true).unwrap();
+        assert!(matches!(
+            CachedirTag.set_state(&dir, false),
+            Err(ApplyError::ForeignCachedirTag)
+        ));
+        assert_eq!(fs::read(dir.join(TAG_NAME)).unwrap(), FOREIGN_TAG);
+    }
+
+    #[test]
+    fn must_not_act_on_foreign_tag() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("target")).unwrap();
+        fs::write(root.join("target").join(TAG_NAME), FOREIGN_TAG).unwrap();
+
+        // No rule matches the tagged directory, but its tag can't be removed anyway.
+        let config = WalkConfig {
+            directories: vec![Directory {
+                path: root.clone(),
+                rules: vec![],
+                from_gitignore: None,
+                protect: Arc::default(),
+            }],
+            skips: HashSet::new(),
+            protect: Arc::default(),
+            min_size: None,
+        };
+        let batch = walk_non_recursive(&root, &config, &CachedirTag, &SkipCache::default());
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn must_not_overwrite_invalid_tag() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("target");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(TAG_NAME), "not a tag").unwrap();
+
+        assert_eq!(CachedirTag.state(&dir).unwrap(), ExcludeState::Included);
+        assert!(matches!(
+            CachedirTag.set_state(&dir, true),
+            Err(ApplyError::IO(e)) if e.kind() == ErrorKind::AlreadyExists
+        ));
+        CachedirTag.set_state(&dir, false).unwrap();
+        assert_eq!(fs::read(dir.join(TAG_NAME)).unwrap(), b"not a tag");
+    }
+
+    #[test]
+    fn must_skip_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join(".packages");
+        fs::write(&file, []).unwrap();
+
+        assert_eq!(CachedirTag.state(&file).unwrap(), ExcludeState::Unsupported);
+        CachedirTag.set_state(&file, true).unwrap();
+        assert_eq!(CachedirTag.state(&file).unwrap(), ExcludeState::Unsupported);
+
+        // Other backends still handle files.
+        let composite = Composite(vec![Box::new(Memory::default()), Box::new(CachedirTag)]);
+        assert_eq!(composite.state(&file).unwrap(), ExcludeState::Included);
+        composite.set_state(&file, true).unwrap();
+        assert_eq!(composite.state(&file).unwrap(), ExcludeState::Excluded);
+    }
+}
@@ -0,0 +1,384 @@
+//! Exclude-list file backend.
+//!
+//! Instead of marking files on disk, excluded paths are collected into an exclude file consumed
+//! by other backup tools.
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use ts_rs::TS;
+
+use crate::backend::{ExcludeState, ExclusionBackend};
+use crate::error::ApplyError;
+use crate::tmutil::ExclusionActionBatch;
+
+const HEADER: &str = "# Generated by tmexclude. Do not edit, changes will be overwritten.";
+
+/// Format of an exclude file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "kebab-case")]
+pub enum ExcludeFileFormat {
+    /// `restic backup --exclude-file`.
+    Restic,
+    /// `borg create --patterns-from`, excluding directories by `- pp:` and files by `- pf:`.
+    Borg,
+    /// `rsync --exclude-from`, with absolute paths anchored at transfer root `/`.
+    Rsync,
+    /// GNU `tar --exclude-from`.
+    Tar,
+}
+
+/// Escape glob metacharacters.
+fn escape_glob(s: &str) -> String {
+    s.chars()
+        .fold(String::with_capacity(s.len()), |mut acc, c| {
+            if matches!(c, '*' | '?' | '[' | '\\') {
+                acc.push('\\');
+            }
+            acc.push(c);
+            acc
+        })
+}
+
+fn unescape_glob(s: &str) -> String {
+    let mut chars = s.chars();
+    let mut acc = String::with_capacity(s.len());
+    while let Some(c) = chars.next() {
+        acc.push(if c == '\\' {
+            chars.next().unwrap_or(c)
+        } else {
+            c
+        });
+    }
+    acc
+}
+
+fn has_wildcard(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+impl ExcludeFileFormat {
+    const fn header(self) -> Option<&'static str> {
+        match self {
+            // tar has no comment syntax.
+            Self::Tar => None,
+            Self::Restic | Self::Borg | Self::Rsync => Some(HEADER),
+        }
+    }
+    /// Render a path as a line of the exclude file.
+    fn render(self, path: &str, is_dir: bool) -> String {
+        match self {
+            // restic expands environment variables in exclude files.
+            Self::Restic => escape_glob(path).replace('$', "$$"),
+            Self::Borg if is_dir => format!("- pp:{}", path),
+            Self::Borg => format!("- pf:{}", path),
+            Self::Rsync => {
+                // rsync only honours backslash escapes in patterns containing wildcards.
+                let pattern = if has_wildcard(path) {
+                    escape_glob(path)
+                } else {
+                    path.to_string()
+                };
+                format!("- {}{}", pattern, if is_dir { "/" } else { "" })
+            }
+            Self::Tar => escape_glob(path),
+        }
+    }
+    /// Parse a line of the exclude file back into a path.
+    fn parse(self, line: &str) -> Option<PathBuf> {
+        if line.is_empty() || (self.header().is_some() && line.starts_with('#')) {
+            return None;
+        }
+        Some(PathBuf::from(match self {
+            Self::Restic => unescape_glob(&line.replace("$$", "$")),
+            Self::Borg => {
+                let pattern = line.strip_prefix("- ")?;
+                pattern
+                    .strip_prefix("pp:")
+                    .or_else(|| pattern.strip_prefix("pf:"))?
+                    .to_string()
+            }
+            Self::Rsync => {
+                let pattern = line.strip_prefix("- ")?;
+                let pattern = if pattern.len() > 1 {
+                    pattern.strip_suffix('/').unwrap_or(pattern)
+                } else {
+                    pattern
+                };
+                if has_wildcard(pattern) {
+                    unescape_glob(pattern)
+                } else {
+                    pattern.to_string()
+                }
+            }
+            Self::Tar => unescape_glob(line),
+        }))
+    }
+}
+
+/// Collects excluded paths into an exclude file of given format.
+///
+/// The file is rewritten atomically each time the set of excluded paths changes. Paths that no
+/// longer exist are pruned on each write.
+#[derive(Debug)]
+pub struct ExcludeFile {
+    path: PathBuf,
+    format: ExcludeFileFormat,
+    entries: Mutex<BTreeSet<PathBuf>>,
+}
+
+impl ExcludeFile {
+    /// Open an exclude file, loading existing entries from it if it exists.
+    ///
+    /// # Errors
+    /// `io::Error` if the file exists but can't be read.
+    pub fn open(path: impl Into<PathBuf>, format: ExcludeFileFormat) -> io::Result<Self> {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Ok(content) => content
+                .lines()
+                .filter_map(|line| format.parse(line))
+                .collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path,
+            format,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    fn write(&self, entries: &mut BTreeSet<PathBuf>) -> io::Result<()> {
+        let mut content = String::new();
+        if let Some(header) = self.format.header() {
+            content.push_str(header);
+            content.push('\n');
+        }
+        entries.retain(|path| match fs::symlink_metadata(path) {
+            Ok(metadata) => {
+                if let Some(s) = path.to_str() {
+                    content.push_str(&self.format.render(s, metadata.is_dir()));
+                    content.push('\n');
+                } else {
+                    warn!("Path {:?} is not valid UTF-8, skipping", path);
+                }
+                true
+            }
+            Err(_) => false,
+        });
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = self.path.with_file_name(tmp_name);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        info!("Exclude file {:?} updated", self.path);
+        Ok(())
+    }
+}
+
+impl ExclusionBackend for ExcludeFile {
+    fn state(&self, path: &Path) -> io::Result<ExcludeState> {
+        fs::symlink_metadata(path)?;
+        Ok(self.entries.lock().contains(path).into())
+    }
+
+    fn set_state(&self, path: &Path, excluded: bool) -> Result<(), ApplyError> {
+        let mut entries = self.entries.lock();
+        let changed = if excluded {
+            entries.insert(path.to_path_buf())
+        } else {
+            entries.remove(path)
+        };
+        if changed {
+            self.write(&mut entries)?;
+        }
+        Ok(())
+    }
+
+    fn apply_batch(&self, batch: ExclusionActionBatch) -> Result<(), HashMap<PathBuf, ApplyError>> {
+        let mut entries = self.entries.lock();
+        let mut changed = false;
+        for path in &batch.add {
+            changed |= entries.insert(path.clone());
+        }
+        for path in &batch.remove {
+            changed |= entries.remove(path);
+        }
+        if !changed {
+            return Ok(());
+        }
+        self.write(&mut entries).map_err(|e| {
+            // Attribute the failure to the exclude file itself.
+            HashMap::from([(self.path.clone(), ApplyError::IO(e))])
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use crate::backend::exclude_file::{ExcludeFile, ExcludeFileFormat, HEADER};
+    use crate::backend::{Composite, ExcludeState, ExclusionBackend, Memory};
+    use crate::error::ApplyError;
+    use crate::tmutil::ExclusionActionBatch;
+
+    /// Counts writes to the exclude file it wraps.
+    #[derive(Debug)]
+    struct CountWrites(ExcludeFile, Arc<AtomicUsize>);
+
+    impl ExclusionBackend for CountWrites {
+        fn state(&self, path: &Path) -> io::Result<ExcludeState> {
+            self.0.state(path)
+        }
+
+        fn set_state(&self, path: &Path, excluded: bool) -> Result<(), ApplyError> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.set_state(path, excluded)
+        }
+
+        fn apply_batch(
+            &self,
+            batch: ExclusionActionBatch,
+        ) -> Result<(), HashMap<PathBuf, ApplyError>> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.apply_batch(batch)
+        }
+    }
+
+    #[test]
+    fn must_render_formats() {
+        let cases = [
+            (
+                ExcludeFileFormat::Restic,
+                "/a/$b/c[1]",
+                true,
+                "/a/$$b/c\\[1]",
+            ),
+            (ExcludeFileFormat::Borg, "/a/b", true, "- pp:/a/b"),
+            (ExcludeFileFormat::Borg, "/a/b", false, "- pf:/a/b"),
+            (ExcludeFileFormat::Rsync, "/a/b", true, "- /a/b/"),
+            (ExcludeFileFormat::Rsync, "/a/b\\c", false, "- /a/b\\c"),
+            (
+                ExcludeFileFormat::Rsync,
+                "/a/b*\\c",
+                false,
+                "- /a/b\\*\\\\c",
+            ),
+            (ExcludeFileFormat::Tar, "/a/b?", true, "/a/b\\?"),
+        ];
+        for (format, path, is_dir, line) in cases {
+            assert_eq!(format.render(path, is_dir), line, "{:?}", format);
+            assert_eq!(
+                format.parse(line).unwrap().to_str().unwrap(),
+                path,
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn must_write_and_reload() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("target")).unwrap();
+        fs::write(root.join(".packages"), []).unwrap();
+        fs::create_dir(root.join("build")).unwrap();
+        let file_path = root.join("out/borg.txt");
+
+        let backend = ExcludeFile::open(&file_path, ExcludeFileFormat::Borg).unwrap();
+        assert_eq!(
+            backend.state(&root.join("target")).unwrap(),
+            ExcludeState::Included
+        );
+        backend
+            .apply_batch(ExclusionActionBatch {
+                add: vec![
+                    root.join("target"),
+                    root.join(".packages"),
+                    root.join("build"),
+                ],
+                remove: vec![],
+                rules: HashMap::new(),
+                recheck: HashMap::new(),
+            })
+            .unwrap();
+        backend.set_state(&root.join("build"), false).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file_path).unwrap(),
+            format!(
+                "{}\n- pf:{}\n- pp:{}\n",
+                HEADER,
+                root.join(".packages").display(),
+                root.join("target").display()
+            )
+        );
+        assert!(!root.join("out/borg.txt.tmp").exists());
+
+        // Entries are loaded back, and missing paths are pruned on next write.
+        fs::remove_file(root.join(".packages")).unwrap();
+        let backend = ExcludeFile::open(&file_path, ExcludeFileFormat::Borg).unwrap();
+        assert_eq!(
+            backend.state(&root.join("target")).unwrap(),
+            ExcludeState::Excluded
+        );
+        backend.set_state(&root.join("build"), true).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file_path).unwrap(),
+            format!(
+                "{}\n- pp:{}\n- pp:{}\n",
+                HEADER,
+                root.join("build").display(),
+                root.join("target").display()
+            )
+        );
+    }
+
+    #[test]
+    fn must_write_once_through_composite() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let paths: Vec<_> = (0..3).map(|i| root.join(i.to_string())).collect();
+        for path in &paths {
+            fs::create_dir(path).unwrap();
+        }
+        let file_path = root.join("restic.txt");
+
+        let exclude_file = ExcludeFile::open(&file_path, ExcludeFileFormat::Restic).unwrap();
+        let writes = Arc::new(AtomicUsize::new(0));
+        let composite = Composite(vec![
+            Box::new(Memory::default()),
+            Box::new(CountWrites(exclude_file, writes.clone())),
+        ]);
+        composite
+            .apply_batch(ExclusionActionBatch {
+                add: paths.clone(),
+                remove: vec![],
+                rules: HashMap::new(),
+                recheck: HashMap::new(),
+            })
+            .unwrap();
+        assert_eq!(writes.load(Ordering::Relaxed), 1);
+        assert_eq!(fs::read_to_string(&file_path).unwrap().lines().count(), 4);
+    }
+}
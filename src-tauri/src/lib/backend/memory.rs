@@ -0,0 +1,76 @@
+//! In-memory backend.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+
+use crate::backend::{ExcludeState, ExclusionBackend};
+use crate::error::ApplyError;
+use crate::tmutil::ExclusionAction;
+
+/// Records exclusion states in memory instead of touching the filesystem.
+///
+/// Paths must still exist on disk to be queried or set, just like other backends. Paths without a
+/// recorded state are considered included. All calls to [`ExclusionBackend::set_state`] are
+/// recorded so that the exact sequence of actions can be inspected later.
+#[derive(Debug, Default)]
+pub struct Memory {
+    states: Mutex<HashMap<PathBuf, ExcludeState>>,
+    history: Mutex<Vec<ExclusionAction>>,
+}
+
+impl Memory {
+    /// Create a backend with given initial states.
+    #[must_use]
+    pub fn new(states: HashMap<PathBuf, ExcludeState>) -> Self {
+        Self {
+            states: Mutex::new(states),
+            history: Mutex::default(),
+        }
+    }
+    /// Override the state of a path without recording it in the history.
+    pub fn seed(&self, path: impl Into<PathBuf>, state: ExcludeState) {
+        self.states.lock().insert(path.into(), state);
+    }
+    /// Get a snapshot of all recorded states.
+    #[must_use]
+    pub fn states(&self) -> HashMap<PathBuf, ExcludeState> {
+        self.states.lock().clone()
+    }
+    /// Get all actions applied to this backend, in order.
+    #[must_use]
+    pub fn history(&self) -> Vec<ExclusionAction> {
+        self.history.lock().clone()
+    }
+    /// Clear the recorded history.
+    pub fn clear_history(&self) {
+        self.history.lock().clear();
+    }
+}
+
+impl ExclusionBackend for Memory {
+    fn state(&self, path: &Path) -> io::Result<ExcludeState> {
+        fs::symlink_metadata(path)?;
+        Ok(self
+            .states
+            .lock()
+            .get(path)
+            .copied()
+            .unwrap_or(ExcludeState::Included))
+    }
+
+    fn set_state(&self, path: &Path, excluded: bool) -> Result<(), ApplyError> {
+        fs::symlink_metadata(path)?;
+        self.states
+            .lock()
+            .insert(path.to_path_buf(), excluded.into());
+        self.history.lock().push(if excluded {
+            ExclusionAction::Add(path.to_path_buf(), None)
+        } else {
+            ExclusionAction::Remove(path.to_path_buf())
+        });
+        Ok(())
+    }
+}
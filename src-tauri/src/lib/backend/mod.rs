@@ -0,0 +1,205 @@
+//! Backends that mark paths as excluded from backups.
+//!
+//! Each backup tool honours a different mark (an extended attribute, a file flag, ...). A backend
+//! knows how to query and set exactly one of these marks, and the walker only talks to backends
+//! through [`ExclusionBackend`].
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use ts_rs::TS;
+
+use crate::error::{ApplyError, ConfigError};
+use crate::tmutil::ExclusionActionBatch;
+
+mod cachedir_tag;
+mod exclude_file;
+mod memory;
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+mod nodump;
+#[cfg(target_os = "macos")]
+mod time_machine;
+
+pub use cachedir_tag::CachedirTag;
+pub use exclude_file::{ExcludeFile, ExcludeFileFormat};
+pub use memory::Memory;
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub use nodump::Nodump;
+#[cfg(target_os = "macos")]
+pub use time_machine::TimeMachine;
+
+/// Exclusion state of a path.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "kebab-case")]
+pub enum ExcludeState {
+    /// The path is currently excluded from backups.
+    Excluded,
+    /// The path is currently included in backups.
+    Included,
+    /// The exclude state of the path is unknown (conflict between backends, e.g. TimeMachine and NODUMP).
+    Inconsistent,
+    /// The backend can't mark this path (e.g. CACHEDIR.TAG on a file). No action is generated for it.
+    Unsupported,
+}
+
+impl ExcludeState {
+    #[must_use]
+    pub const fn is_excluded(&self) -> bool {
+        matches!(self, Self::Excluded)
+    }
+}
+
+impl From<bool> for ExcludeState {
+    fn from(excluded: bool) -> Self {
+        if excluded {
+            Self::Excluded
+        } else {
+            Self::Included
+        }
+    }
+}
+
+/// A mechanism to mark paths as excluded from backups.
+pub trait ExclusionBackend: Debug + Send + Sync {
+    /// Query the exclusion state of a path.
+    ///
+    /// # Errors
+    /// `io::Error` if the state of given path can't be queried.
+    fn state(&self, path: &Path) -> io::Result<ExcludeState>;
+    /// Mark a path as excluded from (or included in) backups.
+    ///
+    /// # Errors
+    /// `ApplyError` if the state of given path can't be set.
+    fn set_state(&self, path: &Path, excluded: bool) -> Result<(), ApplyError>;
+    /// Apply a batch of actions.
+    ///
+    /// # Errors
+    /// Return batched errors if any.
+    fn apply_batch(&self, batch: ExclusionActionBatch) -> Result<(), HashMap<PathBuf, ApplyError>> {
+        let errors: HashMap<_, _> = batch
+            .add
+            .into_iter()
+            .filter_map(|path| {
+                info!("Excluding {:?} from backups", path);
+                self.set_state(&path, true).err().map(|e| (path, e))
+            })
+            .chain(batch.remove.into_iter().filter_map(|path| {
+                info!("Including {:?} in backups", path);
+                self.set_state(&path, false).err().map(|e| (path, e))
+            }))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Kinds of available backends.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "kebab-case")]
+pub enum BackendKind {
+    /// TimeMachine exclusion attribute.
+    TimeMachine,
+    /// NODUMP file flag (`chflags nodump` on macOS, `chattr +d` on Linux), used by DUMP(8) and
+    /// BorgBackup.
+    Nodump,
+    /// `CACHEDIR.TAG` file in excluded directories, used by BorgBackup, restic, GNU tar, etc.
+    CachedirTag,
+}
+
+impl BackendKind {
+    /// Build a backend of this kind.
+    ///
+    /// # Errors
+    /// `ConfigError` if this kind of backend is not supported on this platform.
+    pub fn build(self) -> Result<Box<dyn ExclusionBackend>, ConfigError> {
+        match self {
+            #[cfg(target_os = "macos")]
+            Self::TimeMachine => Ok(Box::new(TimeMachine)),
+            #[cfg(any(target_os = "macos", target_os = "linux"))]
+            Self::Nodump => Ok(Box::new(Nodump)),
+            Self::CachedirTag => Ok(Box::new(CachedirTag)),
+            #[allow(unreachable_patterns)]
+            _ => Err(ConfigError::UnsupportedBackend(self)),
+        }
+    }
+}
+
+/// Backends enabled when none is specified in the config.
+#[must_use]
+pub fn default_backends() -> Vec<BackendKind> {
+    if cfg!(target_os = "macos") {
+        vec![BackendKind::TimeMachine]
+    } else if cfg!(target_os = "linux") {
+        vec![BackendKind::Nodump]
+    } else {
+        vec![]
+    }
+}
+
+/// Build a backend that drives all given backends at once.
+///
+/// # Errors
+/// `ConfigError` if no backend is given.
+pub fn combine_backends(
+    mut backends: Vec<Box<dyn ExclusionBackend>>,
+) -> Result<Arc<dyn ExclusionBackend>, ConfigError> {
+    match backends.len() {
+        0 => Err(ConfigError::NoBackend),
+        1 => Ok(Arc::from(backends.remove(0))),
+        _ => Ok(Arc::new(Composite(backends))),
+    }
+}
+
+/// A backend that forwards to multiple backends.
+///
+/// A path is considered excluded only if all backends supporting it agree on it.
+#[derive(Debug)]
+pub struct Composite(Vec<Box<dyn ExclusionBackend>>);
+
+impl ExclusionBackend for Composite {
+    fn state(&self, path: &Path) -> io::Result<ExcludeState> {
+        self.0
+            .iter()
+            .try_fold(ExcludeState::Unsupported, |acc, backend| {
+                Ok(match (acc, backend.state(path)?) {
+                    (acc, ExcludeState::Unsupported) => acc,
+                    (ExcludeState::Unsupported, state) => state,
+                    (acc, state) if acc == state => acc,
+                    _ => ExcludeState::Inconsistent,
+                })
+            })
+    }
+
+    fn set_state(&self, path: &Path, excluded: bool) -> Result<(), ApplyError> {
+        self.0
+            .iter()
+            .try_for_each(|backend| backend.set_state(path, excluded))
+    }
+
+    /// Forward the whole batch to each backend, so that backends batching their writes (e.g.
+    /// exclude files) still do so.
+    fn apply_batch(&self, batch: ExclusionActionBatch) -> Result<(), HashMap<PathBuf, ApplyError>> {
+        let mut errors = HashMap::new();
+        for backend in &self.0 {
+            if let Err(e) = backend.apply_batch(batch.clone()) {
+                for (path, e) in e {
+                    errors.entry(path).or_insert(e);
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! NODUMP flag backend.
+//!
+//! On macOS the flag is the `UF_NODUMP` file flag set by `chflags(2)`. On Linux it's the
+//! `FS_NODUMP_FL` inode flag set by `chattr +d`, which is supported by ext2/3/4, btrfs, xfs and
+//! some other filesystems.
+use std::io;
+use std::path::Path;
+
+use crate::backend::{ExcludeState, ExclusionBackend};
+use crate::error::ApplyError;
+
+/// Excludes paths from DUMP(8) and BorgBackup by setting the NODUMP flag.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Nodump;
+
+impl ExclusionBackend for Nodump {
+    fn state(&self, path: &Path) -> io::Result<ExcludeState> {
+        Ok(imp::is_nodump(path)?.into())
+    }
+
+    fn set_state(&self, path: &Path, excluded: bool) -> Result<(), ApplyError> {
+        Ok(imp::set_nodump(path, excluded)?)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    const UF_NODUMP: u32 = 0x0000_0001;
+
+    fn c_path(path: &Path) -> CString {
+        CString::new(path.as_os_str().as_bytes()).expect("path contains null byte")
+    }
+
+    fn stat(path: &Path) -> io::Result<libc::stat> {
+        let path = c_path(path);
+        unsafe {
+            let mut stat: libc::stat = std::mem::zeroed();
+            let ret = libc::stat(path.as_ptr(), &mut stat);
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(stat)
+        }
+    }
+
+    pub fn is_nodump(path: &Path) -> io::Result<bool> {
+        Ok(stat(path)?.st_flags & UF_NODUMP != 0)
+    }
+
+    pub fn set_nodump(path: &Path, value: bool) -> io::Result<()> {
+        let mut stat = stat(path)?;
+        if value {
+            stat.st_flags |= UF_NODUMP;
+        } else {
+            stat.st_flags &= !UF_NODUMP;
+        }
+        let path = c_path(path);
+        let ret = unsafe { libc::chflags(path.as_ptr(), stat.st_flags) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::fs::{self, File, OpenOptions};
+    use std::io;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    use libc::{c_int, FS_IOC_GETFLAGS, FS_IOC_SETFLAGS};
+
+    /// `FS_NODUMP_FL` from `<linux/fs.h>`.
+    const FS_NODUMP_FL: c_int = 0x0000_0040;
+
+    /// Open a file to operate on its inode flags.
+    ///
+    /// Only regular files and directories are supported, because ioctls on device files go to
+    /// their drivers, and symlinks can't be opened at all.
+    fn open(path: &Path) -> io::Result<File> {
+        let file_type = fs::symlink_metadata(path)?.file_type();
+        if !file_type.is_file() && !file_type.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "inode flags are only supported on regular files and directories",
+            ));
+        }
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW)
+            .open(path)
+    }
+
+    fn get_flags(file: &File) -> io::Result<c_int> {
+        // The kernel reads and writes an `int` despite the `long` in the request definition.
+        let mut flags: c_int = 0;
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_GETFLAGS, &mut flags) };
+        if ret == 0 {
+            Ok(flags)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn is_nodump(path: &Path) -> io::Result<bool> {
+        Ok(get_flags(&open(path)?)? & FS_NODUMP_FL != 0)
+    }
+
+    pub fn set_nodump(path: &Path, value: bool) -> io::Result<()> {
+        let file = open(path)?;
+        let flags = get_flags(&file)?;
+        let new_flags = if value {
+            flags | FS_NODUMP_FL
+        } else {
+            flags & !FS_NODUMP_FL
+        };
+        if new_flags == flags {
+            return Ok(());
+        }
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_SETFLAGS, &new_flags) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use std::fs;
+        use std::io;
+
+        use tempfile::TempDir;
+
+        use crate::backend::{ExcludeState, ExclusionBackend, Nodump};
+
+        #[test]
+        fn must_set_nodump() {
+            let temp_dir = TempDir::new().unwrap();
+            let file = temp_dir.path().join("file");
+            let dir = temp_dir.path().join("dir");
+            fs::write(&file, []).unwrap();
+            fs::create_dir(&dir).unwrap();
+
+            match Nodump.state(&file) {
+                Ok(state) => assert_eq!(state, ExcludeState::Included),
+                // The filesystem of temp dir doesn't support inode flags (e.g. tmpfs).
+                Err(e) if e.raw_os_error() == Some(libc::ENOTTY) => return,
+                Err(e) => panic!("{}", e),
+            }
+
+            for path in [&file, &dir] {
+                Nodump.set_state(path, true).unwrap();
+                assert_eq!(Nodump.state(path).unwrap(), ExcludeState::Excluded);
+                Nodump.set_state(path, false).unwrap();
+                assert_eq!(Nodump.state(path).unwrap(), ExcludeState::Included);
+            }
+
+            let symlink = temp_dir.path().join("symlink");
+            std::os::unix::fs::symlink(&file, &symlink).unwrap();
+            assert_eq!(
+                Nodump.state(&symlink).unwrap_err().kind(),
+                io::ErrorKind::Unsupported
+            );
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! `TimeMachine` backend.
+use std::io;
+use std::path::Path;
+use std::ptr;
+
+use core_foundation::base::{CFTypeRef, TCFType, ToVoid};
+use core_foundation::error::{CFError, CFErrorRef};
+use core_foundation::number::{kCFBooleanFalse, kCFBooleanTrue};
+use core_foundation::string::CFStringRef;
+use core_foundation::url;
+use core_foundation::url::{kCFURLIsExcludedFromBackupKey, CFURL};
+use tap::TapFallible;
+use tracing::warn;
+
+use crate::backend::{ExcludeState, ExclusionBackend};
+use crate::error::ApplyError;
+
+/// Excludes paths from `TimeMachine` backups by setting their `kCFURLIsExcludedFromBackupKey`
+/// resource property.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TimeMachine;
+
+impl ExclusionBackend for TimeMachine {
+    fn state(&self, path: &Path) -> io::Result<ExcludeState> {
+        Ok(
+            xattr::get(path, "com.apple.metadata:com_apple_backup_excludeItem")
+                .tap_err(|e| warn!("Error when querying xattr of file {:?}: {}", path, e))?
+                .is_some()
+                .into(),
+        )
+    }
+
+    fn set_state(&self, path: &Path, excluded: bool) -> Result<(), ApplyError> {
+        let objc_value = unsafe {
+            if excluded {
+                kCFBooleanTrue
+            } else {
+                kCFBooleanFalse
+            }
+        };
+        let url = CFURL::from_path(path, false).ok_or(ApplyError::InvalidURL)?;
+        set_resource_property_for_key(
+            &url,
+            unsafe { kCFURLIsExcludedFromBackupKey },
+            objc_value.to_void(),
+        )?;
+        Ok(())
+    }
+}
+
+fn set_resource_property_for_key(
+    url: &CFURL,
+    key: CFStringRef,
+    value: CFTypeRef,
+) -> Result<(), CFError> {
+    let mut err: CFErrorRef = ptr::null_mut();
+    let result = unsafe {
+        url::CFURLSetResourcePropertyForKey(url.as_concrete_TypeRef(), key, value, &mut err)
+    };
+    if result == 0 {
+        let err = unsafe { CFError::wrap_under_create_rule(err) };
+        Err(err)
+    } else {
+        Ok(())
+    }
+}
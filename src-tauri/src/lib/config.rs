@@ -1,32 +1,48 @@
 //! Defines all needed configs and views to them.
 //!
 //! The config is synchronized by design so it can be hot-reloaded.
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
-use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
 use std::io::ErrorKind;
-use std::ops::ControlFlow;
+use std::ops::{ControlFlow, Deref};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{fs, iter};
 
 use directories::BaseDirs;
 use itertools::Itertools;
 use maplit::hashset;
-use serde::{Deserialize, Serialize};
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize, Serializer};
 use tap::TapFallible;
-use tracing::warn;
+use tracing::{error, info, warn};
 use ts_rs::TS;
 
+use crate::age::Age;
+use crate::backend::{
+    combine_backends, default_backends, BackendKind, ExcludeFile, ExcludeFileFormat,
+    ExclusionBackend,
+};
 use crate::error::{ConfigError, ConfigIOError};
+use crate::include;
+use crate::migration::{self, CURRENT_VERSION, VERSION_KEY};
+use crate::patch::patch;
+use crate::pattern::{PatternError, Patterns};
+use crate::skip_cache::ContentCache;
+use crate::source_map::Segment;
+use crate::validate::{validate_source, Diagnostic};
 
 /// Main config type used throughout the application.
 #[derive(Debug, Clone)]
 pub struct Config {
     /// Do not include files to backups if conditions are not met. Defaults to `false`.
     pub no_include: bool,
-    /// Support NODUMP flag. Defaults to `false`.
-    pub support_dump: bool,
+    /// Backend used to query and set exclusion states.
+    pub backend: Arc<dyn ExclusionBackend>,
     /// Configs related to walking, including interested directories and corresponding rules.
     pub walk: Arc<WalkConfig>,
 }
@@ -35,13 +51,16 @@ impl TryFrom<PreConfig> for Config {
     type Error = ConfigError;
 
     fn try_from(value: PreConfig) -> Result<Self, Self::Error> {
+        let rules = value.all_rules().into_owned();
         Ok(Self {
             no_include: value.no_include,
-            support_dump: value.support_dump,
+            backend: value.backend()?,
             walk: Arc::new(WalkConfig::from(
                 value.directories,
-                &value.rules,
+                &rules,
                 value.skips,
+                value.protect,
+                value.min_size,
             )?),
         })
     }
@@ -54,6 +73,10 @@ pub struct WalkConfig {
     pub directories: Vec<Directory>,
     /// Directories to be skipped when scanning and watching.
     pub skips: HashSet<PathBuf>,
+    /// Paths never to be excluded in any directory.
+    pub protect: Arc<Protect>,
+    /// Paths smaller than this (in bytes) are not excluded, unless rules say otherwise.
+    pub min_size: Option<u64>,
 }
 
 /// An interested directory and its corresponding rules.
@@ -62,19 +85,472 @@ pub struct Directory {
     /// The interested directory.
     pub path: PathBuf,
     /// Rules bound to this directory.
-    pub rules: Vec<Rule>,
+    pub rules: Vec<Arc<CompiledRule>>,
+    /// Exclude directories ignored by git, if set.
+    pub from_gitignore: Option<FromGitignore>,
+    /// Paths never to be excluded in this directory, even if some rules match.
+    pub protect: Arc<Protect>,
+}
+
+/// Exclude directories ignored by `.gitignore` files and `.git/info/exclude`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct FromGitignore {
+    /// Only ignored directories at least this large (in bytes) are excluded.
+    pub min_size: u64,
+}
+
+/// Paths never to be excluded, even if some rules match.
+///
+/// Absolute paths protect exactly that path. Other entries are patterns matched against file names,
+/// like `excludes` in rules.
+#[derive(Debug, Clone, Default)]
+pub struct Protect {
+    entries: Vec<PathBuf>,
+    paths: HashSet<PathBuf>,
+    patterns: Patterns,
+}
+
+impl Protect {
+    /// Compile given entries. `name` identifies the list in error messages.
+    ///
+    /// # Errors
+    /// `ConfigError` if some pattern is invalid.
+    pub fn new(name: &str, entries: Vec<PathBuf>) -> Result<Self, ConfigError> {
+        let (absolute, relative): (Vec<_>, Vec<_>) =
+            entries.iter().partition(|entry| entry.is_absolute());
+        let patterns =
+            Patterns::new(relative, &[]).map_err(|PatternError { pattern, source }| {
+                ConfigError::InvalidPattern {
+                    rule: name.to_string(),
+                    pattern,
+                    source,
+                }
+            })?;
+        Ok(Self {
+            paths: absolute
+                .into_iter()
+                // Walked paths are canonicalized, so protected paths must be too.
+                .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+                .collect(),
+            patterns,
+            entries,
+        })
+    }
+    /// Whether nothing is protected.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.patterns.is_empty()
+    }
+    /// Check if `dir/name` is protected.
+    #[must_use]
+    pub fn is_protected(&self, dir: &Path, name: &Path) -> bool {
+        (!self.paths.is_empty() && self.paths.contains(&dir.join(name)))
+            || self.patterns.is_match(name)
+            || self.patterns.nested_bases(dir, name).next().is_some()
+    }
+}
+
+impl PartialEq for Protect {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl Eq for Protect {}
+
+impl Hash for Protect {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.entries.hash(state);
+    }
 }
 
 /// Rules to be applied on a specific set of directories.
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Default, TS)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, TS)]
 #[ts(export, export_to = "../src/bindings/")]
 #[serde(rename_all = "kebab-case")]
 pub struct Rule {
-    /// Paths to be excluded.
+    /// Paths to be excluded. Globs like `*.egg-info` and nested paths like `android/app/build` are
+    /// allowed.
+    #[serde(default)]
     pub excludes: Vec<PathBuf>,
     /// Exclude paths if *any* of these paths exist in the same directory as the path to be excluded.
+    /// Globs like `*.xcodeproj` are allowed.
     #[serde(default)]
     pub if_exists: Vec<PathBuf>,
+    /// Regexes matching names of paths to be excluded, for cases globs can't express.
+    #[serde(default)]
+    pub excludes_regex: Vec<String>,
+    /// Exclude paths if any path matching *any* of these regexes exists in the same directory.
+    #[serde(default)]
+    pub if_exists_regex: Vec<String>,
+    /// Exclude paths only if *none* of these paths exist in the same directory.
+    #[serde(default)]
+    pub if_not_exists: Vec<PathBuf>,
+    /// Exclude paths only if *all* of these paths exist in the same directory.
+    #[serde(default)]
+    pub if_all_exist: Vec<PathBuf>,
+    /// Exclude paths only if a file in the same directory contains given pattern.
+    #[serde(default)]
+    pub if_file_contains: Option<FileContains>,
+    /// Exclude paths only if their total size is at least this many bytes. Defaults to the global
+    /// `min-size`.
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub min_size: Option<u64>,
+    /// Exclude paths only if they haven't been modified for this long, e.g. `30d`.
+    #[serde(default)]
+    #[ts(type = "string | null")]
+    pub older_than: Option<Age>,
+    /// Exclude paths only if they have been modified within this long, e.g. `1d`.
+    #[serde(default)]
+    #[ts(type = "string | null")]
+    pub newer_than: Option<Age>,
+    /// Whose modification time `older-than` and `newer-than` are evaluated against.
+    #[serde(default)]
+    pub age_of: AgeOf,
+    /// Disabled rules are validated but never applied. Defaults to `true`.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Human readable description of the rule.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Tags of the rule. Directories may reference rules by tag.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+const fn default_enabled() -> bool {
+    true
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            excludes: vec![],
+            if_exists: vec![],
+            excludes_regex: vec![],
+            if_exists_regex: vec![],
+            if_not_exists: vec![],
+            if_all_exist: vec![],
+            if_file_contains: None,
+            min_size: None,
+            older_than: None,
+            newer_than: None,
+            age_of: AgeOf::default(),
+            enabled: true,
+            description: None,
+            tags: vec![],
+        }
+    }
+}
+
+/// A file in the same directory as the path to be excluded, and a regex its content must match.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct FileContains {
+    /// Path to the file, relative to the directory.
+    pub file: PathBuf,
+    /// Regex to search in the file.
+    pub pattern: String,
+}
+
+/// Whose modification time age conditions of a rule are evaluated against.
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Hash, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "kebab-case")]
+pub enum AgeOf {
+    /// The path to be excluded.
+    #[default]
+    Candidate,
+    /// The most recently modified sentinel in `if-exists` and `if-all-exist`, or the path to be
+    /// excluded if the rule has no sentinel.
+    Sentinel,
+}
+
+/// Identifies a rule by its name, and the union rules it's reached through.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct RuleId {
+    /// Name of the concrete rule.
+    pub name: String,
+    /// Names of union rules leading to the concrete rule, outermost first.
+    pub ancestry: Vec<String>,
+}
+
+impl RuleId {
+    /// Id attributed to exclusions of directories ignored by git.
+    #[must_use]
+    pub fn from_gitignore() -> Self {
+        Self {
+            name: String::from("from-gitignore"),
+            ancestry: vec![],
+        }
+    }
+}
+
+impl Display for RuleId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for union in &self.ancestry {
+            write!(f, "{union} > ")?;
+        }
+        f.write_str(&self.name)
+    }
+}
+
+/// Why a rule does or doesn't match an entry.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleVerdict {
+    /// The entry should be excluded.
+    Matched,
+    /// The entry doesn't match any pattern in `excludes`.
+    NotMatched,
+    /// Required sentinels in `if-exists` or `if-all-exist` are missing.
+    MissingSentinel,
+    /// Some sentinel in `if-not-exists` exists.
+    ForbiddenSentinel,
+    /// The file in `if-file-contains` doesn't contain the pattern.
+    ContentMismatch,
+    /// The path is smaller than `min-size`.
+    TooSmall,
+    /// The path has been modified within `older-than`.
+    TooNew,
+    /// The path hasn't been modified within `newer-than`.
+    TooOld,
+}
+
+/// A [`Rule`] with its patterns compiled.
+///
+/// Compiled rules are compared and hashed by their source rule.
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    id: RuleId,
+    rule: Rule,
+    exclude_patterns: Patterns,
+    if_exists_patterns: Patterns,
+    if_not_exists_patterns: Patterns,
+    if_all_exist_patterns: Vec<Patterns>,
+    file_contains: Option<(PathBuf, Regex)>,
+}
+
+impl CompiledRule {
+    /// Compile a rule.
+    ///
+    /// # Errors
+    /// `ConfigError` if some pattern is invalid. `name` is used to locate the rule in the error.
+    pub fn new(name: &str, rule: Rule) -> Result<Self, ConfigError> {
+        let invalid = |e: PatternError| ConfigError::InvalidPattern {
+            rule: name.to_string(),
+            pattern: e.pattern,
+            source: e.source,
+        };
+        Ok(Self {
+            exclude_patterns: Patterns::new(&rule.excludes, &rule.excludes_regex)
+                .map_err(invalid)?,
+            if_exists_patterns: Patterns::new(&rule.if_exists, &rule.if_exists_regex)
+                .map_err(invalid)?,
+            if_not_exists_patterns: Patterns::new(&rule.if_not_exists, &[]).map_err(invalid)?,
+            if_all_exist_patterns: rule
+                .if_all_exist
+                .iter()
+                .map(|pattern| Patterns::new([pattern], &[]))
+                .try_collect()
+                .map_err(invalid)?,
+            file_contains: rule
+                .if_file_contains
+                .as_ref()
+                .map(|FileContains { file, pattern }| {
+                    Regex::new(pattern)
+                        .map(|regex| (file.clone(), regex))
+                        .map_err(|e| ConfigError::InvalidPattern {
+                            rule: name.to_string(),
+                            pattern: pattern.clone(),
+                            source: Box::new(e),
+                        })
+                })
+                .transpose()?,
+            id: RuleId {
+                name: name.to_string(),
+                ancestry: vec![],
+            },
+            rule,
+        })
+    }
+    /// The source rule.
+    #[must_use]
+    pub const fn rule(&self) -> &Rule {
+        &self.rule
+    }
+    /// Name and ancestry of this rule.
+    #[must_use]
+    pub const fn id(&self) -> &RuleId {
+        &self.id
+    }
+    /// Minimum size of paths excluded by this rule, if set on the rule itself.
+    #[must_use]
+    pub const fn min_size(&self) -> Option<u64> {
+        self.rule.min_size
+    }
+    /// This rule, reached through given union rule.
+    fn within(&self, union: &str) -> Self {
+        let mut rule = self.clone();
+        rule.id.ancestry.insert(0, union.to_string());
+        rule
+    }
+    /// Check if entry `name` in `dir` should be excluded, given all entries in `dir`.
+    ///
+    /// Nested excludes like `android/app/build` are checked against the directory they are
+    /// resolved from, i.e. where the sentinels should live. File contents are only read if all
+    /// other conditions hold.
+    #[must_use]
+    pub fn is_match<V>(
+        &self,
+        dir: &Path,
+        name: &Path,
+        shallow_list: &HashMap<PathBuf, V>,
+        contents: &ContentCache,
+    ) -> bool {
+        self.verdict(dir, name, shallow_list, contents) == RuleVerdict::Matched
+    }
+    /// Tell whether entry `name` in `dir` should be excluded, and why not if it shouldn't.
+    ///
+    /// See [`CompiledRule::is_match`].
+    #[must_use]
+    pub fn verdict<V>(
+        &self,
+        dir: &Path,
+        name: &Path,
+        shallow_list: &HashMap<PathBuf, V>,
+        contents: &ContentCache,
+    ) -> RuleVerdict {
+        let mut verdict = RuleVerdict::NotMatched;
+        if self.exclude_patterns.is_match(name) {
+            verdict = self.conditions_verdict(
+                dir,
+                |patterns| patterns.matches_any(dir, shallow_list),
+                contents,
+            );
+            if verdict == RuleVerdict::Matched {
+                return verdict;
+            }
+        }
+        for base in self.exclude_patterns.nested_bases(dir, name) {
+            verdict =
+                self.conditions_verdict(base, |patterns| patterns.matches_any_in(base), contents);
+            if verdict == RuleVerdict::Matched {
+                return verdict;
+            }
+        }
+        verdict
+    }
+    /// Check age conditions on entry `name` in `dir` at `now`.
+    ///
+    /// Besides the verdict, returns when it's going to change as the entry ages, if it will. Paths
+    /// whose modification time can't be read never satisfy age conditions.
+    #[must_use]
+    pub fn age_verdict(
+        &self,
+        dir: &Path,
+        name: &Path,
+        now: SystemTime,
+    ) -> (RuleVerdict, Option<SystemTime>) {
+        let within = |age: Age, mtime: SystemTime| {
+            now.duration_since(mtime).unwrap_or_default() < age.duration()
+        };
+        let (older_than, newer_than) = (self.rule.older_than, self.rule.newer_than);
+        if older_than.is_none() && newer_than.is_none() {
+            return (RuleVerdict::Matched, None);
+        }
+        let Some(mtime) = self.reference_mtime(dir, name) else {
+            let verdict = if older_than.is_some() {
+                RuleVerdict::TooNew
+            } else {
+                RuleVerdict::TooOld
+            };
+            return (verdict, None);
+        };
+        match (older_than, newer_than) {
+            (Some(older_than), _) if within(older_than, mtime) => (
+                RuleVerdict::TooNew,
+                mtime.checked_add(older_than.duration()),
+            ),
+            (_, Some(newer_than)) if !within(newer_than, mtime) => (RuleVerdict::TooOld, None),
+            (_, newer_than) => (
+                RuleVerdict::Matched,
+                newer_than.and_then(|newer_than| mtime.checked_add(newer_than.duration())),
+            ),
+        }
+    }
+    /// Modification time age conditions are evaluated against, for entry `name` in `dir`.
+    fn reference_mtime(&self, dir: &Path, name: &Path) -> Option<SystemTime> {
+        let mtime = |path: &Path| path.symlink_metadata().and_then(|m| m.modified()).ok();
+        let candidate = || mtime(&dir.join(name));
+        if self.rule.age_of == AgeOf::Candidate {
+            return candidate();
+        }
+        // Sentinels live where nested excludes are resolved from.
+        let bases = iter::once(dir)
+            .filter(|_| self.exclude_patterns.is_match(name))
+            .chain(self.exclude_patterns.nested_bases(dir, name));
+        let sentinels: Vec<_> = bases
+            .flat_map(|base| {
+                iter::once(&self.if_exists_patterns)
+                    .chain(&self.if_all_exist_patterns)
+                    .flat_map(move |patterns| patterns.matches_in(base))
+                    .map(move |sentinel| base.join(sentinel))
+            })
+            .collect();
+        if sentinels.is_empty() {
+            candidate()
+        } else {
+            sentinels.iter().filter_map(|path| mtime(path)).max()
+        }
+    }
+    /// Check sentinel conditions in `dir`, given a way to check whether any entry matches some
+    /// patterns.
+    fn conditions_verdict(
+        &self,
+        dir: &Path,
+        exists: impl Fn(&Patterns) -> bool,
+        contents: &ContentCache,
+    ) -> RuleVerdict {
+        if !(self.if_exists_patterns.is_empty() || exists(&self.if_exists_patterns))
+            || !self.if_all_exist_patterns.iter().all(&exists)
+        {
+            RuleVerdict::MissingSentinel
+        } else if exists(&self.if_not_exists_patterns) {
+            RuleVerdict::ForbiddenSentinel
+        } else if matches!(&self.file_contains, Some((file, regex)) if !contents.contains(&dir.join(file), regex))
+        {
+            RuleVerdict::ContentMismatch
+        } else {
+            RuleVerdict::Matched
+        }
+    }
+}
+
+impl Deref for CompiledRule {
+    type Target = Rule;
+
+    fn deref(&self) -> &Self::Target {
+        &self.rule
+    }
+}
+
+impl PartialEq for CompiledRule {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.rule == other.rule
+    }
+}
+
+impl Eq for CompiledRule {}
+
+impl Hash for CompiledRule {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.rule.hash(state);
+    }
 }
 
 fn max_common_path(path_1: impl AsRef<Path>, path_2: impl AsRef<Path>) -> PathBuf {
@@ -123,12 +599,23 @@ fn get_root(directories: &[Directory]) -> Option<PathBuf> {
         })
 }
 
-fn dfs_union_rules<'a, 'b>(
-    cache: &mut HashMap<String, HashSet<Rule>>,
-    rules: &'a HashMap<String, PreRule>,
-    node: &'b str,
-    mut visited: HashSet<&'b str>,
-) -> Result<HashSet<Rule>, ConfigError> {
+/// Order rules resolved from one name, so that the rule a path is attributed to doesn't depend on
+/// hash order: rules reached through fewer unions come first, then they are ordered by id.
+#[allow(clippy::mutable_key_type)]
+fn sorted_by_id(rules: HashSet<Arc<CompiledRule>>) -> impl Iterator<Item = Arc<CompiledRule>> {
+    rules
+        .into_iter()
+        .sorted_by_cached_key(|rule| (rule.id.ancestry.len(), rule.id.to_string()))
+}
+
+// Compiled rules are hashed by their ids and source rules, which are immutable.
+#[allow(clippy::mutable_key_type)]
+fn dfs_union_rules<'a>(
+    cache: &mut HashMap<String, HashSet<Arc<CompiledRule>>>,
+    rules: &HashMap<String, PreRule>,
+    node: &'a str,
+    mut visited: HashSet<&'a str>,
+) -> Result<HashSet<Arc<CompiledRule>>, ConfigError> {
     if let Some(hit) = cache.get(node) {
         return Ok(hit.clone());
     }
@@ -140,15 +627,24 @@ fn dfs_union_rules<'a, 'b>(
         .get(node)
         .ok_or_else(|| ConfigError::Rule(node.to_string()))?;
     match resolved {
-        PreRule::Concrete(rule) => Ok(hashset![rule.clone()]),
+        PreRule::Concrete(rule) => {
+            // Compile disabled rules too so that they are validated.
+            let compiled = CompiledRule::new(node, rule.clone())?;
+            Ok(if rule.enabled {
+                hashset![Arc::new(compiled)]
+            } else {
+                hashset![]
+            })
+        }
         PreRule::Union(referenced) => {
             visited.insert(node);
             referenced
                 .iter()
-                .map(|node| dfs_union_rules(cache, rules, node, visited.clone()))
+                .map(|child| dfs_union_rules(cache, rules, child, visited.clone()))
                 .try_fold(hashset![/* can have rules later */], |mut acc, x| {
                     x.map(|x| {
-                        acc.extend(x);
+                        // Record the union in ancestry of rules reached through it.
+                        acc.extend(x.iter().map(|rule| Arc::new(rule.within(node))));
                         acc
                     })
                 })
@@ -159,6 +655,32 @@ fn dfs_union_rules<'a, 'b>(
     })
 }
 
+/// Resolve enabled rules tagged with `tag`.
+#[allow(clippy::mutable_key_type)]
+fn resolve_tag(
+    cache: &mut HashMap<String, HashSet<Arc<CompiledRule>>>,
+    rules: &HashMap<String, PreRule>,
+    tag: &str,
+) -> Result<Vec<Arc<CompiledRule>>, ConfigError> {
+    let names = rules
+        .iter()
+        .filter(|(_, rule)| matches!(rule, PreRule::Concrete(rule) if rule.tags.iter().any(|t| t == tag)))
+        .map(|(name, _)| name.as_str())
+        .sorted_unstable()
+        .collect_vec();
+    if names.is_empty() {
+        return Err(ConfigError::Tag(tag.to_string()));
+    }
+    let mut resolved = vec![];
+    for name in names {
+        resolved.extend(
+            sorted_by_id(dfs_union_rules(cache, rules, name, hashset![])?)
+                .map(|rule| Arc::new(rule.within(&format!("#{tag}")))),
+        );
+    }
+    Ok(resolved)
+}
+
 fn follow_symlinks(path: PathBuf) -> impl Iterator<Item = PathBuf> {
     let mut visited = hashset![path.clone()];
     iter::successors(Some(path), move |path| {
@@ -181,7 +703,14 @@ fn follow_symlinks(path: PathBuf) -> impl Iterator<Item = PathBuf> {
     })
 }
 
-fn absolute(path: impl AsRef<Path>) -> PathBuf {
+pub(crate) fn expand_all(paths: Vec<String>) -> Vec<PathBuf> {
+    paths
+        .into_iter()
+        .map(|path| PathBuf::from(shellexpand::tilde(&path).as_ref()))
+        .collect()
+}
+
+pub(crate) fn absolute(path: impl AsRef<Path>) -> PathBuf {
     std::env::current_dir()
         .expect("current dir must exist")
         .join(path)
@@ -192,6 +721,8 @@ impl WalkConfig {
         directories: Vec<PreDirectory>,
         rules: &HashMap<String, PreRule>,
         skips: Vec<String>,
+        protect: Vec<String>,
+        min_size: Option<u64>,
     ) -> Result<Self, ConfigError> {
         let mut cache = HashMap::new();
         Ok(Self {
@@ -208,10 +739,16 @@ impl WalkConfig {
                         })
                         .try_fold(vec![], |mut acc, x| {
                             x.map(|x| {
-                                acc.extend(x.into_iter());
+                                acc.extend(sorted_by_id(x));
                                 acc
                             })
                         })
+                        .and_then(|mut acc| {
+                            for tag in &pre_directory.tags {
+                                acc.extend(resolve_tag(&mut cache, rules, tag)?);
+                            }
+                            Ok(acc)
+                        })
                         .and_then(|rules| {
                             PathBuf::from(shellexpand::tilde(&pre_directory.path).as_ref())
                                 .canonicalize() // canonicalize here because fsevent api always returns absolute paths
@@ -233,7 +770,23 @@ impl WalkConfig {
                                 })
                                 .map(|path| (path, rules))
                         })
-                        .map(|(path, rules)| Directory { path, rules })
+                        .and_then(|(path, rules)| {
+                            Ok(Directory {
+                                protect: Arc::new(Protect::new(
+                                    &format!("{}#protect", pre_directory.path),
+                                    expand_all(pre_directory.protect),
+                                )?),
+                                path,
+                                rules,
+                                from_gitignore: pre_directory.from_gitignore.then_some(
+                                    FromGitignore {
+                                        min_size: pre_directory
+                                            .gitignore_min_size
+                                            .unwrap_or(DEFAULT_GITIGNORE_MIN_SIZE),
+                                    },
+                                ),
+                            })
+                        })
                 })
                 .try_collect()?,
             skips: skips
@@ -241,6 +794,8 @@ impl WalkConfig {
                 .flat_map(|path| follow_symlinks(PathBuf::from(shellexpand::tilde(&path).as_ref())))
                 .map(absolute)
                 .collect(),
+            protect: Arc::new(Protect::new("protect", expand_all(protect))?),
+            min_size,
         })
     }
 
@@ -259,30 +814,148 @@ impl WalkConfig {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, TS)]
 #[ts(export, export_to = "../src/bindings/")]
 #[serde(rename_all = "kebab-case")]
 pub struct PreConfig {
+    /// Version of the config schema. See [`CURRENT_VERSION`].
+    #[serde(default = "current_version")]
+    pub version: u32,
+    /// Rule packs to include, relative to the config file. Their rules are namespaced by the
+    /// pack, e.g. `rules/cargo`.
+    #[serde(default)]
+    pub(crate) include: Vec<String>,
     #[serde(default)]
     pub no_include: bool,
     #[serde(default)]
     pub support_dump: bool,
+    /// Backends to apply exclusions with. Platform defaults are used if unset, and they are never
+    /// written back, so that a config file can be shared between platforms.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub backends: Option<Vec<BackendKind>>,
     #[serde(default)]
-    directories: Vec<PreDirectory>,
+    pub(crate) exclude_files: Vec<PreExcludeFile>,
     #[serde(default)]
-    skips: Vec<String>,
+    pub(crate) directories: Vec<PreDirectory>,
     #[serde(default)]
-    rules: HashMap<String, PreRule>,
+    pub(crate) skips: Vec<String>,
+    /// Paths or patterns never to be excluded, even if some rules match.
+    #[serde(default)]
+    pub(crate) protect: Vec<String>,
+    /// Paths smaller than this (in bytes) are not excluded, unless rules say otherwise.
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub(crate) min_size: Option<u64>,
+    /// Rules are written sorted by name, so that saved configs are stable.
+    #[serde(default, serialize_with = "serialize_sorted")]
+    pub(crate) rules: HashMap<String, PreRule>,
+    /// Rules loaded from `include`. They are never written back to the config file.
+    #[serde(skip)]
+    #[ts(skip)]
+    pub(crate) included_rules: HashMap<String, PreRule>,
+}
+
+const fn current_version() -> u32 {
+    CURRENT_VERSION
+}
+
+fn serialize_sorted<S: Serializer>(
+    rules: &HashMap<String, PreRule>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(rules.iter().sorted_unstable_by_key(|(name, _)| *name))
+}
+
+impl PreConfig {
+    /// Load rule packs listed in `include`, relative to the config file at `path`.
+    ///
+    /// # Errors
+    /// Returns error if some pack can't be read or parsed, or some pack includes itself.
+    pub fn resolve_includes(&mut self, path: &Path) -> Result<(), ConfigIOError> {
+        self.included_rules = include::resolve(&self.include, path)?;
+        Ok(())
+    }
+    /// Local rules together with included ones. Local rules win over included ones.
+    pub(crate) fn all_rules(&self) -> Cow<'_, HashMap<String, PreRule>> {
+        if self.included_rules.is_empty() {
+            return Cow::Borrowed(&self.rules);
+        }
+        let mut rules = self.included_rules.clone();
+        rules.extend(self.rules.iter().map(|(k, v)| (k.clone(), v.clone())));
+        Cow::Owned(rules)
+    }
+    /// Kinds of backends enabled by this config.
+    ///
+    /// NODUMP backend is implied by `support-dump`.
+    #[must_use]
+    pub fn backend_kinds(&self) -> Vec<BackendKind> {
+        let mut kinds = self.backends.clone().unwrap_or_else(default_backends);
+        if self.support_dump && !kinds.contains(&BackendKind::Nodump) {
+            kinds.push(BackendKind::Nodump);
+        }
+        kinds
+    }
+    /// Build the backend described by this config.
+    ///
+    /// # Errors
+    /// `ConfigError` if no backend is enabled, some backend is not supported on this platform, or
+    /// some exclude file can't be read.
+    pub fn backend(&self) -> Result<Arc<dyn ExclusionBackend>, ConfigError> {
+        let mut backends: Vec<_> = self
+            .backend_kinds()
+            .into_iter()
+            .map(BackendKind::build)
+            .try_collect()?;
+        for exclude_file in &self.exclude_files {
+            let path = absolute(shellexpand::tilde(&exclude_file.path).as_ref());
+            backends.push(Box::new(
+                ExcludeFile::open(path, exclude_file.format).map_err(|e| {
+                    ConfigError::InvalidPath {
+                        path: exclude_file.path.clone(),
+                        source: e,
+                    }
+                })?,
+            ));
+        }
+        combine_backends(backends)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+/// An exclude file to be generated for other backup tools.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, TS)]
 #[ts(export, export_to = "../src/bindings/")]
-pub struct PreDirectory {
+pub struct PreExcludeFile {
     path: String,
-    rules: Vec<String>,
+    format: ExcludeFileFormat,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+/// Ignored directories smaller than this are not excluded, unless configured otherwise.
+const DEFAULT_GITIGNORE_MIN_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "kebab-case")]
+pub struct PreDirectory {
+    pub(crate) path: String,
+    pub(crate) rules: Vec<String>,
+    /// Exclude directories ignored by `.gitignore` files, layered with rules.
+    #[serde(default)]
+    from_gitignore: bool,
+    /// Only ignored directories at least this large (in bytes) are excluded. Defaults to 1 MiB.
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    gitignore_min_size: Option<u64>,
+    /// Paths or patterns never to be excluded in this directory, even if some rules match.
+    #[serde(default)]
+    pub(crate) protect: Vec<String>,
+    /// Apply rules with any of these tags too.
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, TS)]
 #[ts(export, export_to = "../src/bindings/")]
 #[serde(untagged)]
 pub enum PreRule {
@@ -290,17 +963,6 @@ pub enum PreRule {
     Union(Vec<String>),
 }
 
-#[derive(Debug)]
-struct AdhocError(String);
-
-impl Display for AdhocError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        Display::fmt(&self.0, f)
-    }
-}
-
-impl Error for AdhocError {}
-
 const DEFAULT_CONFIG: &str = include_str!("../../../config.example.yaml");
 
 /// Config manager handles config file loading and saving.
@@ -329,46 +991,241 @@ impl ConfigManager {
 
         Ok(Self { path })
     }
+    /// Create a config manager on given config file.
+    ///
+    /// Unlike [`ConfigManager::new`], the file is not created if it doesn't exist.
+    #[must_use]
+    pub const fn with_path(path: PathBuf) -> Self {
+        Self { path }
+    }
+    /// Path to the config file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
     /// Load config from file.
     ///
+    /// Configs written by older versions are migrated in memory, and are persisted in the current
+    /// version on the next save. Included rule packs are loaded too.
+    ///
     /// # Errors
-    /// Returns error if the config file can't be read or can't be parsed.
+    /// Returns error if the config file or some included pack can't be read or can't be parsed, or
+    /// the config is written by a newer version.
     pub fn load(&self) -> Result<PreConfig, ConfigIOError> {
         let content = fs::read_to_string(&self.path).map_err(ConfigIOError::ReadConfig)?;
-        serde_yaml::from_str(&content).map_err(|e| ConfigIOError::Deserialize(Box::new(e)))
+        let mut value: serde_yaml::Value =
+            serde_yaml::from_str(&content).map_err(|e| ConfigIOError::Deserialize(Box::new(e)))?;
+        let version = migration::migrate(&mut value)?;
+        if version != CURRENT_VERSION {
+            info!(
+                "Migrated config from version {} to {}",
+                version, CURRENT_VERSION
+            );
+        }
+        let mut config: PreConfig =
+            serde_yaml::from_value(value).map_err(|e| ConfigIOError::Deserialize(Box::new(e)))?;
+        config.resolve_includes(&self.path)?;
+        Ok(config)
+    }
+    /// Load config from file, backing it up and resetting it to default if it's broken.
+    ///
+    /// Configs that are fine by themselves are never reset: those failing to include some pack,
+    /// and those written by a newer version, which this version can't tell apart from broken ones.
+    /// Returns the path to the backup if the config has been reset.
+    ///
+    /// # Errors
+    /// Returns error if the config can't be loaded and must not be reset, or can't be backed up.
+    pub fn load_or_reset(&self) -> Result<(PreConfig, Option<PathBuf>), ConfigIOError> {
+        match self.load() {
+            Ok(pre_config) => Ok((pre_config, None)),
+            Err(
+                e @ (ConfigIOError::Include { .. }
+                | ConfigIOError::IncludeLoop(_)
+                | ConfigIOError::UnsupportedVersion(_)),
+            ) => Err(e),
+            Err(e) => {
+                let backup = self.backup_and_reset()?;
+                error!(
+                    ?e,
+                    ?backup,
+                    "Failed to load config, backed up and reset to default"
+                );
+                Ok((self.load()?, Some(backup)))
+            }
+        }
+    }
+    /// Validate the config file, reporting all problems found.
+    ///
+    /// # Errors
+    /// Returns error if the config file can't be read.
+    pub fn validate(&self) -> Result<Vec<Diagnostic>, ConfigIOError> {
+        let content = fs::read_to_string(&self.path).map_err(ConfigIOError::ReadConfig)?;
+        Ok(validate_source(&content, &self.path))
     }
+    /// Overwrite the config file with the default config.
+    ///
+    /// # Errors
+    /// Returns error if the config file can't be written.
     pub fn reset(&self) -> Result<(), ConfigIOError> {
         fs::write(&self.path, DEFAULT_CONFIG).map_err(ConfigIOError::WriteConfig)?;
         Ok(())
     }
+    /// Move the config file aside and write the default config in its place.
+    ///
+    /// Used when the config file can't be loaded, so that hand-written configs are never lost.
+    /// Returns the path to the backup.
+    ///
+    /// # Errors
+    /// Returns error if the config file can't be moved or the default config can't be written.
+    pub fn backup_and_reset(&self) -> Result<PathBuf, ConfigIOError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{timestamp}.bak"));
+        let backup = self.path.with_file_name(name);
+        fs::rename(&self.path, &backup).map_err(ConfigIOError::Backup)?;
+        self.reset()?;
+        Ok(backup)
+    }
     /// Save config to file.
     ///
+    /// Only changed parts of the config file are rewritten, so that comments, key order and
+    /// formatting are kept. The whole file is rewritten if it can't be patched, e.g. when it's not
+    /// a valid config. Included rules are not written back, only the `include` list is.
+    ///
     /// # Errors
     /// Returns error if the config file can't be written.
     pub fn save(&self, config: &PreConfig) -> Result<(), ConfigIOError> {
-        let content =
-            serde_yaml::to_string(config).map_err(|e| ConfigIOError::Serialize(Box::new(e)))?;
+        let patched = fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|source| patch_config(&source, config));
+        let content = match patched {
+            Some(content) => content,
+            None => {
+                serde_yaml::to_string(config).map_err(|e| ConfigIOError::Serialize(Box::new(e)))?
+            }
+        };
         fs::write(&self.path, content).map_err(ConfigIOError::WriteConfig)
     }
 }
 
+/// Patch the source of a config file to hold `config`.
+///
+/// Returns `None` if the source can't be patched in place.
+fn patch_config(source: &str, config: &PreConfig) -> Option<String> {
+    let original: serde_yaml::Value = serde_yaml::from_str(source).ok()?;
+    let mut old = canonical(source)?;
+    // Stamp the current version on configs written by older versions.
+    if let Some(mapping) = old.as_mapping_mut() {
+        match original.get(VERSION_KEY) {
+            Some(version) => mapping.insert(VERSION_KEY.into(), version.clone()),
+            None => mapping.remove(VERSION_KEY),
+        };
+    }
+    let new = serde_yaml::to_value(config).ok()?;
+    let patched = patch(source, &old, &new, &prune_inserted)?;
+    // Never write a config that reads differently than the one being saved.
+    if canonical(&patched)? == new {
+        Some(patched)
+    } else {
+        warn!("Failed to patch config in place, rewriting the whole file");
+        None
+    }
+}
+
+/// Drop default entries of rules and directories about to be written to a config file.
+fn prune_inserted(path: &[Segment], value: &mut serde_yaml::Value) {
+    match path {
+        [Segment::Key(key)] if key == "rules" => {
+            if let Some(rules) = value.as_mapping_mut() {
+                rules.values_mut().for_each(prune_defaults::<Rule>);
+            }
+        }
+        [Segment::Key(key), _] if key == "rules" => prune_defaults::<Rule>(value),
+        [Segment::Key(key)] if key == "directories" => {
+            if let Some(directories) = value.as_sequence_mut() {
+                directories
+                    .iter_mut()
+                    .for_each(prune_defaults::<PreDirectory>);
+            }
+        }
+        [Segment::Key(key), _] if key == "directories" => prune_defaults::<PreDirectory>(value),
+        _ => {}
+    }
+}
+
+/// Drop entries of a mapping that `T` fills in by default anyway.
+fn prune_defaults<T: Serialize + DeserializeOwned>(value: &mut serde_yaml::Value) {
+    let Some(mapping) = value.as_mapping() else {
+        return;
+    };
+    let full = value.clone();
+    for key in mapping.keys().cloned().collect_vec() {
+        let mut pruned = value.clone();
+        if let Some(mapping) = pruned.as_mapping_mut() {
+            mapping.remove(&key);
+        }
+        let reads_as = serde_yaml::from_value::<T>(pruned.clone())
+            .ok()
+            .and_then(|t| serde_yaml::to_value(t).ok());
+        if reads_as.as_ref() == Some(&full) {
+            *value = pruned;
+        }
+    }
+}
+
+/// What the source of a config file reads as, with default values filled in.
+fn canonical(source: &str) -> Option<serde_yaml::Value> {
+    let original: serde_yaml::Value = serde_yaml::from_str(source).ok()?;
+    let mut value = original.clone();
+    migration::migrate(&mut value).ok()?;
+    // Migrated configs can't be located in their sources.
+    if !migration::only_version_changed(original, value.clone()) {
+        return None;
+    }
+    let config: PreConfig = serde_yaml::from_value(value).ok()?;
+    serde_yaml::to_value(config).ok()
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::HashSet;
+    use std::error::Error;
+    use std::fmt::{Display, Formatter};
     use std::io::ErrorKind;
     use std::path::{Path, PathBuf};
     use std::str::FromStr;
     use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
     use std::{env, fs};
 
     use itertools::Itertools;
     use maplit::hashset;
     use serde::{Deserialize, Deserializer};
+    use tempfile::TempDir;
 
-    use crate::config::{get_paths, get_root, AdhocError, Config, Directory, Rule, WalkConfig};
-    use crate::error::ConfigError;
+    use crate::backend::{default_backends, BackendKind};
+    use crate::config::{
+        get_paths, get_root, AgeOf, CompiledRule, Config, ConfigManager, Directory, PreRule, Rule,
+        RuleVerdict, WalkConfig, DEFAULT_CONFIG,
+    };
+    use crate::error::{ConfigError, ConfigIOError};
+    use crate::migration::CURRENT_VERSION;
     use crate::PreConfig;
 
+    #[derive(Debug)]
+    struct AdhocError(String);
+
+    impl Display for AdhocError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            Display::fmt(&self.0, f)
+        }
+    }
+
+    impl Error for AdhocError {}
+
     macro_rules! path {
         ($s: expr) => {
             PathBuf::from_str($s).unwrap()
@@ -384,11 +1241,19 @@ mod test {
         };
     }
 
+    macro_rules! compiled {
+        ($name: expr, $rule: expr) => {
+            Arc::new(CompiledRule::new($name, $rule).unwrap())
+        };
+    }
+
     macro_rules! directory {
         ($s: expr) => {
             Directory {
                 path: path!($s),
                 rules: vec![],
+                from_gitignore: None,
+                protect: Arc::default(),
             }
         };
     }
@@ -404,11 +1269,13 @@ mod test {
             .map_err(|e| ConfigError::Deserialize(Box::new(AdhocError(e.to_string()))))?;
         Ok(Config {
             no_include: pre_config.no_include,
-            support_dump: pre_config.support_dump,
+            backend: pre_config.backend()?,
             walk: Arc::new(WalkConfig::from(
                 pre_config.directories,
                 &pre_config.rules,
                 pre_config.skips,
+                pre_config.protect,
+                pre_config.min_size,
             )?),
         })
     }
@@ -423,15 +1290,16 @@ mod test {
 
             let rule_a = Rule {
                 excludes: vec![path!("exclude_a")],
-                if_exists: vec![],
+                ..Default::default()
             };
             let rule_b = Rule {
                 excludes: vec![path!("exclude_b")],
-                if_exists: vec![],
+                ..Default::default()
             };
             let rule_d = Rule {
                 excludes: vec![path!("exclude_d1"), path!("exclude_d2")],
                 if_exists: vec![path!("a"), path!("b")],
+                ..Default::default()
             };
 
             assert!(config.no_include);
@@ -441,14 +1309,23 @@ mod test {
                     directories: vec![
                         Directory {
                             path: cwd_path!("tests/mock_dirs/path_a"),
-                            rules: vec![rule_a, rule_b.clone()],
+                            rules: vec![
+                                compiled!("rule_a", rule_a),
+                                compiled!("rule_b", rule_b.clone()),
+                            ],
+                            from_gitignore: None,
+                            protect: Arc::default(),
                         },
                         Directory {
                             path: cwd_path!("tests/mock_dirs/path_b"),
-                            rules: vec![rule_b, rule_d],
+                            rules: vec![compiled!("rule_b", rule_b), compiled!("rule_d", rule_d)],
+                            from_gitignore: None,
+                            protect: Arc::default(),
                         },
                     ],
                     skips: hashset![cwd_path!("tests/mock_dirs/path_b")],
+                    protect: Arc::default(),
+                    min_size: None,
                 })
             );
         });
@@ -485,6 +1362,37 @@ mod test {
         });
     }
 
+    #[test]
+    fn must_record_rule_ancestry() {
+        with_directory(|| {
+            let config = config_from(serde_yaml::Deserializer::from_str(include_str!(
+                "../../tests/configs/inherit_rule.yaml"
+            )))
+            .expect("must parse config");
+
+            let ids = |i: usize| {
+                config.walk.directories[i]
+                    .rules
+                    .iter()
+                    .map(|rule| rule.id().to_string())
+                    .collect_vec()
+            };
+            // Rules reached through multiple unions are kept apart, and those reached through
+            // fewer unions come first.
+            assert_eq!(
+                ids(0),
+                [
+                    "main > a",
+                    "main > c",
+                    "main > b > a",
+                    "main > b > c",
+                    "main > b > d"
+                ]
+            );
+            assert_eq!(ids(1), ["b > a", "b > c", "b > d", "e"]);
+        });
+    }
+
     #[test]
     fn must_fail_inherit_rule_loop() {
         let error = config_from(serde_yaml::Deserializer::from_str(include_str!(
@@ -511,6 +1419,117 @@ mod test {
         }
     }
 
+    #[test]
+    fn must_resolve_tagged_rules() {
+        with_directory(|| {
+            let config = config_from(serde_yaml::Deserializer::from_str(include_str!(
+                "../../tests/configs/tagged_rules.yaml"
+            )))
+            .expect("must parse config");
+
+            let ids = |i: usize| {
+                config.walk.directories[i]
+                    .rules
+                    .iter()
+                    .map(|rule| rule.id().to_string())
+                    .collect_vec()
+            };
+            // Disabled rules are skipped.
+            assert_eq!(ids(0), ["main > a"]);
+            assert_eq!(ids(1), ["#build > a"]);
+        });
+    }
+
+    #[test]
+    fn must_validate_disabled_rule() {
+        let error = config_from(serde_yaml::Deserializer::from_str(include_str!(
+            "../../tests/configs/invalid_disabled_rule.yaml"
+        )))
+        .expect_err("must fail");
+
+        match error {
+            ConfigError::InvalidPattern { rule, .. } => assert_eq!(rule, "a"),
+            _ => panic!("Error type mismatch"),
+        }
+    }
+
+    #[test]
+    fn must_fail_missing_tag() {
+        let error = config_from(serde_yaml::Deserializer::from_str(include_str!(
+            "../../tests/configs/missing_tag.yaml"
+        )))
+        .expect_err("must fail");
+
+        match error {
+            ConfigError::Tag(tag) => assert_eq!(tag, "missing"),
+            _ => panic!("Error type mismatch"),
+        }
+    }
+
+    #[test]
+    fn must_fail_invalid_pattern() {
+        let error = config_from(serde_yaml::Deserializer::from_str(include_str!(
+            "../../tests/configs/invalid_pattern.yaml"
+        )))
+        .expect_err("must fail");
+
+        match error {
+            ConfigError::InvalidPattern { rule, pattern, .. } => {
+                assert_eq!(rule, "b");
+                assert_eq!(pattern, "*.[a-");
+            }
+            _ => panic!("Error type mismatch"),
+        }
+    }
+
+    #[test]
+    fn must_parse_regex_only_rule() {
+        with_directory(|| {
+            let config = config_from(serde_yaml::Deserializer::from_str(include_str!(
+                "../../tests/configs/regex_only.yaml"
+            )))
+            .expect("must parse config");
+
+            let rule = &config.walk.directories[0].rules[0];
+            assert!(rule.rule.excludes.is_empty());
+            assert_eq!(rule.rule.excludes_regex, ["^build-(debug|release)$"]);
+        });
+    }
+
+    #[test]
+    fn must_fail_invalid_regex() {
+        let pre_config: PreConfig =
+            serde_yaml::from_str(include_str!("../../tests/configs/invalid_regex.yaml")).unwrap();
+        let error = Config::try_from(pre_config).expect_err("must fail");
+
+        match error {
+            ConfigError::InvalidPattern { rule, pattern, .. } => {
+                assert_eq!(rule, "a");
+                assert_eq!(pattern, "^build-(debug|release");
+            }
+            _ => panic!("Error type mismatch"),
+        }
+    }
+
+    #[test]
+    fn must_parse_protect() {
+        with_directory(|| {
+            let config = config_from(serde_yaml::Deserializer::from_str(include_str!(
+                "../../tests/configs/protect.yaml"
+            )))
+            .expect("must parse config");
+
+            let dir = cwd_path!("tests/mock_dirs/path_a");
+            let protect = &config.walk.protect;
+            assert!(protect.is_protected(&dir, Path::new("a.keep")));
+            assert!(!protect.is_protected(&dir, Path::new("build")));
+
+            let protect = &config.walk.directories[0].protect;
+            assert!(protect.is_protected(&dir.join("important"), Path::new("build")));
+            assert!(!protect.is_protected(&dir, Path::new("build")));
+        });
+    }
+
     #[test]
     fn must_fail_broken_dir() {
         with_directory(|| {
@@ -677,4 +1696,192 @@ mod test {
             panic!("Unsupported platform");
         }
     }
+
+    #[test]
+    fn must_check_age() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        fs::write(dir.join("Cargo.toml"), "").unwrap();
+        fs::create_dir(dir.join("target")).unwrap();
+        let target = Path::new("target");
+        let rule = |older_than: Option<&str>, newer_than: Option<&str>, age_of| {
+            CompiledRule::new(
+                "",
+                Rule {
+                    excludes: vec![path!("target")],
+                    if_exists: vec![path!("Cargo.toml")],
+                    older_than: older_than.map(|age| age.parse().unwrap()),
+                    newer_than: newer_than.map(|age| age.parse().unwrap()),
+                    age_of,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(2 * 24 * 60 * 60);
+
+        for age_of in [AgeOf::Candidate, AgeOf::Sentinel] {
+            let stale = rule(Some("1d"), None, age_of);
+            let (verdict, recheck_at) = stale.age_verdict(dir, target, now);
+            assert_eq!(verdict, RuleVerdict::TooNew);
+            assert!(recheck_at.unwrap() > now && recheck_at.unwrap() < later);
+            assert_eq!(
+                stale.age_verdict(dir, target, later),
+                (RuleVerdict::Matched, None)
+            );
+        }
+
+        let fresh = rule(None, Some("1d"), AgeOf::Candidate);
+        let (verdict, recheck_at) = fresh.age_verdict(dir, target, now);
+        assert_eq!(verdict, RuleVerdict::Matched);
+        assert!(recheck_at.is_some());
+        assert_eq!(
+            fresh.age_verdict(dir, target, later),
+            (RuleVerdict::TooOld, None)
+        );
+
+        assert_eq!(
+            rule(None, None, AgeOf::Candidate).age_verdict(dir, target, now),
+            (RuleVerdict::Matched, None)
+        );
+        assert_eq!(
+            rule(Some("1d"), None, AgeOf::Candidate).age_verdict(dir, Path::new("missing"), later),
+            (RuleVerdict::TooNew, None)
+        );
+    }
+
+    #[test]
+    fn must_back_up_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("tmexclude.yaml");
+        let manager = ConfigManager::with_path(path.clone());
+
+        // Unversioned configs are migrated.
+        fs::write(&path, "no-include: true\n").unwrap();
+        let pre_config = manager.load().unwrap();
+        assert_eq!(pre_config.version, CURRENT_VERSION);
+        assert!(pre_config.no_include);
+
+        // Configs written by newer versions are left alone.
+        let content = format!("version: {}\n", CURRENT_VERSION + 1);
+        fs::write(&path, &content).unwrap();
+        assert!(matches!(
+            manager.load_or_reset(),
+            Err(ConfigIOError::UnsupportedVersion(_))
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        // Unreadable configs are moved aside instead of being overwritten.
+        let content = "directories: 42\n";
+        fs::write(&path, content).unwrap();
+        let (pre_config, backup) = manager.load_or_reset().unwrap();
+        let backup = backup.unwrap();
+        assert_eq!(backup.parent(), Some(temp_dir.path()));
+        assert_eq!(fs::read_to_string(backup).unwrap(), content);
+        assert_eq!(fs::read_to_string(&path).unwrap(), DEFAULT_CONFIG);
+        assert_eq!(manager.load().unwrap(), pre_config);
+    }
+
+    #[test]
+    fn must_not_write_default_backends() {
+        let pre_config: PreConfig = serde_yaml::from_str("no-include: true\n").unwrap();
+        assert_eq!(pre_config.backend_kinds(), default_backends());
+        assert!(!serde_yaml::to_string(&pre_config)
+            .unwrap()
+            .contains("backends"));
+
+        let pre_config: PreConfig = serde_yaml::from_str("backends: [ cachedir-tag ]\n").unwrap();
+        assert_eq!(pre_config.backend_kinds(), [BackendKind::CachedirTag]);
+        assert!(serde_yaml::to_string(&pre_config)
+            .unwrap()
+            .contains("backends:\n- cachedir-tag\n"));
+    }
+
+    #[test]
+    fn must_not_inline_included_rules() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("tmexclude.yaml");
+        let manager = ConfigManager::with_path(path.clone());
+        fs::write(
+            temp_dir.path().join("pack.yaml"),
+            "rules:\n  a: { excludes: [ from-pack ] }\n  b: { excludes: [ from-pack ] }\n",
+        )
+        .unwrap();
+        fs::write(
+            &path,
+            format!(
+                "include: [ pack.yaml ]\ndirectories:\n  - path: {}\n    rules: [ pack/a, pack/b ]\nrules:\n  pack/b: {{ excludes: [ local ] }}\n",
+                temp_dir.path().display()
+            ),
+        )
+        .unwrap();
+
+        let pre_config = manager.load().unwrap();
+        // Local rules win over included ones.
+        let rules = pre_config.all_rules();
+        assert!(
+            matches!(&rules["pack/a"], PreRule::Concrete(rule) if rule.excludes == [path!("from-pack")])
+        );
+        assert!(
+            matches!(&rules["pack/b"], PreRule::Concrete(rule) if rule.excludes == [path!("local")])
+        );
+        assert!(Config::try_from(pre_config.clone()).is_ok());
+
+        manager.save(&pre_config).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("pack.yaml"));
+        assert!(!content.contains("from-pack"));
+        assert_eq!(manager.load().unwrap(), pre_config);
+    }
+
+    #[test]
+    fn must_keep_comments_on_save() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("tmexclude.yaml");
+        let manager = ConfigManager::with_path(path.clone());
+        fs::write(&path, DEFAULT_CONFIG).unwrap();
+
+        let mut pre_config = manager.load().unwrap();
+        pre_config.no_include = false;
+        pre_config.rules.remove("Maven");
+        if let Some(PreRule::Union(rules)) = pre_config.rules.get_mut("Development") {
+            rules.retain(|rule| rule != "Maven");
+            rules.push(String::from("Extra"));
+        }
+        pre_config.rules.insert(
+            String::from("Extra"),
+            PreRule::Concrete(Rule {
+                excludes: vec![path!("extra")],
+                ..Default::default()
+            }),
+        );
+        manager.save(&pre_config).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        for line in DEFAULT_CONFIG.lines() {
+            if line.trim_start().starts_with('#') {
+                assert!(content.contains(line), "missing comment: {line}");
+            }
+        }
+        assert!(content.contains("no-include: false\n"));
+        assert!(!content.contains("Maven"));
+        assert!(content.contains("    - Extra\n"));
+        assert!(content.contains("  Extra:\n    excludes:\n    - extra\n"));
+        // Only changed lines are touched.
+        let changed = diff_lines(DEFAULT_CONFIG, &content);
+        assert!(changed <= 10, "{changed} lines changed");
+        assert_eq!(manager.load().unwrap(), pre_config);
+
+        // Unchanged configs are saved as is.
+        manager.save(&pre_config).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    fn diff_lines(a: &str, b: &str) -> usize {
+        let a: HashSet<_> = a.lines().collect();
+        let b: HashSet<_> = b.lines().collect();
+        a.symmetric_difference(&b).count()
+    }
 }
@@ -2,11 +2,16 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::path::PathBuf;
 
+#[cfg(target_os = "macos")]
 use core_foundation::error::CFError;
+use itertools::Itertools;
 use serde::Serialize;
 use thiserror::Error;
 use ts_rs::TS;
 
+use crate::backend::BackendKind;
+use crate::validate::Diagnostic;
+
 /// Error that may occur when loading a config.
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Error)]
@@ -17,6 +22,9 @@ pub enum ConfigError {
     /// Missing rule.
     #[error("Missing rule: {0}")]
     Rule(String),
+    /// No rule has the tag.
+    #[error("No rule is tagged with {0}")]
+    Tag(String),
     /// No directories in config.
     #[error("No directory to scan")]
     NoDirectory,
@@ -28,11 +36,30 @@ pub enum ConfigError {
         /// The underlying IO error.
         source: std::io::Error,
     },
+    /// Invalid pattern in a rule.
+    #[error("Invalid pattern in rule {rule}: {pattern}")]
+    InvalidPattern {
+        /// Name of the rule.
+        rule: String,
+        /// The invalid pattern.
+        pattern: String,
+        /// The underlying parse error.
+        source: Box<dyn Error + Send + Sync>,
+    },
     /// Missing rule.
     #[error("Loop found in rules. Rendezvous point: {0}")]
     Loop(String),
+    /// No backend to apply exclusions with.
+    #[error("No backend to apply exclusions with")]
+    NoBackend,
+    /// Backend is not supported on this platform.
+    #[error("Backend is not supported on this platform: {0:?}")]
+    UnsupportedBackend(BackendKind),
     #[error("Error when reading/writing config file")]
     Load(#[from] ConfigIOError),
+    /// Validation found some errors.
+    #[error("Invalid config:\n{}", .0.iter().join("\n"))]
+    Invalid(Vec<Diagnostic>),
 }
 
 #[derive(Debug, Error)]
@@ -49,16 +76,31 @@ pub enum ConfigIOError {
     Deserialize(#[source] Box<dyn Error + Send + Sync>),
     #[error("Error when serializing config file")]
     Serialize(#[source] Box<dyn Error + Send + Sync>),
+    #[error("Config version {0} is not supported, it may be written by a newer version")]
+    UnsupportedVersion(u32),
+    #[error("Failed to back up config")]
+    Backup(#[source] std::io::Error),
+    #[error("Failed to include {path}")]
+    Include {
+        /// The included path, as written in the config.
+        path: String,
+        source: Box<ConfigIOError>,
+    },
+    #[error("Include loop found: {} includes itself", .0.display())]
+    IncludeLoop(PathBuf),
 }
 
 #[derive(Debug, Error)]
 pub enum ApplyError {
     #[error("URL is invalid")]
     InvalidURL,
+    #[cfg(target_os = "macos")]
     #[error("Failed to apply rule: {0}")]
     PropertyFail(#[from] CFError),
     #[error("Failed to apply rule: {0}")]
     IO(#[from] std::io::Error),
+    #[error("CACHEDIR.TAG is not created by tmexclude, refusing to remove it")]
+    ForeignCachedirTag,
 }
 
 #[derive(Serialize, TS)]
@@ -0,0 +1,78 @@
+//! Events emitted by a mission, and sinks to receive them.
+use std::sync::Arc;
+
+use crossbeam::channel::Sender;
+use serde_json::{Map, Value};
+#[cfg(feature = "gui")]
+use tauri::{AppHandle, Manager};
+#[cfg(feature = "gui")]
+use tracing::warn;
+
+use crate::config::PreConfig;
+use crate::mission::ScanStatus;
+use crate::validate::Diagnostic;
+
+/// An event emitted by a mission.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Status of full scan changed.
+    ScanStatusChanged(ScanStatus),
+    /// Config changed.
+    ConfigChanged(Arc<PreConfig>),
+    /// Config file is edited on disk, but can't be reloaded. Current config is kept.
+    ConfigReloadFailed(Vec<Diagnostic>),
+    /// Persistent properties changed.
+    PropertiesChanged(Map<String, Value>),
+}
+
+impl Event {
+    /// Name of the event, as seen by the frontend.
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::ScanStatusChanged(_) => "scan_status_changed",
+            Self::ConfigChanged(_) => "config_changed",
+            Self::ConfigReloadFailed(_) => "config_reload_failed",
+            Self::PropertiesChanged(_) => "properties_changed",
+        }
+    }
+}
+
+/// Receiver of mission events.
+pub trait EventSink: Send + Sync {
+    /// Emit an event.
+    fn emit(&self, event: Event);
+}
+
+/// Broadcast events to all tauri windows.
+#[cfg(feature = "gui")]
+impl EventSink for AppHandle {
+    fn emit(&self, event: Event) {
+        let name = event.name();
+        let r = match event {
+            Event::ScanStatusChanged(status) => self.emit_all(name, status),
+            Event::ConfigChanged(config) => self.emit_all(name, config),
+            Event::ConfigReloadFailed(diagnostics) => self.emit_all(name, diagnostics),
+            Event::PropertiesChanged(properties) => self.emit_all(name, properties),
+        };
+        if let Err(e) = r {
+            warn!("Failed to broadcast event {}: {}", name, e);
+        }
+    }
+}
+
+/// Send events through a channel.
+impl EventSink for Sender<Event> {
+    fn emit(&self, event: Event) {
+        // The receiver may be gone, and nobody cares about events then.
+        drop(self.send(event));
+    }
+}
+
+/// Discard all events.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NoopSink;
+
+impl EventSink for NoopSink {
+    fn emit(&self, _event: Event) {}
+}
@@ -0,0 +1,123 @@
+//! Honour `.gitignore` files when walking.
+//!
+//! Ignore files are loaded lazily, one directory at a time, and cached so that each of them is
+//! read at most once per walk.
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use moka::sync::Cache;
+use tracing::warn;
+
+const CACHE_MAX_CAPACITY: u64 = 4096;
+
+/// Ignore rules declared in a single directory.
+#[derive(Debug)]
+struct Level {
+    matcher: Gitignore,
+    is_repo_root: bool,
+}
+
+impl Level {
+    fn load(dir: &Path) -> Self {
+        let git_dir = dir.join(".git");
+        let is_repo_root = git_dir.symlink_metadata().is_ok();
+
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut sources = vec![];
+        if git_dir.is_dir() {
+            // Lines in `.gitignore` take precedence over `.git/info/exclude`, so add it first.
+            sources.push(git_dir.join("info").join("exclude"));
+        }
+        sources.push(dir.join(".gitignore"));
+        for source in sources {
+            if let Some(e) = builder.add(&source) {
+                if !matches!(e.io_error(), Some(e) if e.kind() == io::ErrorKind::NotFound) {
+                    warn!("Error when reading ignore file {:?}: {}", source, e);
+                }
+            }
+        }
+        let matcher = builder.build().unwrap_or_else(|e| {
+            warn!("Error when building ignore rules in {:?}: {}", dir, e);
+            Gitignore::empty()
+        });
+
+        Self {
+            matcher,
+            is_repo_root,
+        }
+    }
+}
+
+/// Cache of ignore rules declared in visited directories.
+#[derive(Clone)]
+pub struct GitignoreCache(Arc<Cache<PathBuf, Arc<Level>>>);
+
+impl Default for GitignoreCache {
+    fn default() -> Self {
+        Self(Arc::new(Cache::new(CACHE_MAX_CAPACITY)))
+    }
+}
+
+impl GitignoreCache {
+    fn level(&self, dir: &Path) -> Arc<Level> {
+        self.0
+            .get_with(dir.to_path_buf(), || Arc::new(Level::load(dir)))
+    }
+    /// Check if a path is ignored by git.
+    ///
+    /// Ignore files in deeper directories take precedence, and negations are respected. Paths
+    /// outside git repositories are never ignored.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut decision = None;
+        for dir in path.ancestors().skip(1) {
+            let level = self.level(dir);
+            if decision.is_none() {
+                decision = match level.matcher.matched(path, is_dir) {
+                    Match::Ignore(_) => Some(true),
+                    Match::Whitelist(_) => Some(false),
+                    Match::None => None,
+                };
+            }
+            if level.is_repo_root {
+                return decision.unwrap_or(false);
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::gitignore::GitignoreCache;
+
+    #[test]
+    fn must_respect_nested_and_negated() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+
+        fs::create_dir_all(root.join("repo/.git/info")).unwrap();
+        fs::write(root.join("repo/.git/info/exclude"), "scratch/\n").unwrap();
+        fs::write(root.join("repo/.gitignore"), "build/\n*.log\n").unwrap();
+        fs::create_dir_all(root.join("repo/sub")).unwrap();
+        fs::write(root.join("repo/sub/.gitignore"), "!build/\ncache/\n").unwrap();
+        fs::write(root.join(".gitignore"), "outside/\n").unwrap();
+
+        let cache = GitignoreCache::default();
+        assert!(cache.is_ignored(&root.join("repo/build"), true));
+        assert!(cache.is_ignored(&root.join("repo/scratch"), true));
+        assert!(cache.is_ignored(&root.join("repo/a.log"), false));
+        assert!(!cache.is_ignored(&root.join("repo/src"), true));
+        // Negated in nested ignore file.
+        assert!(!cache.is_ignored(&root.join("repo/sub/build"), true));
+        assert!(cache.is_ignored(&root.join("repo/sub/cache"), true));
+        // Not in a repository.
+        assert!(!cache.is_ignored(&root.join("outside"), true));
+    }
+}
@@ -0,0 +1,182 @@
+//! Rule packs shared between configs.
+//!
+//! A config may include rule packs, e.g. `include: [~/dotfiles/tmexclude/rules.yaml]`. Rules in a
+//! pack are namespaced by the pack, so rule `cargo` in `rules.yaml` is referred to as
+//! `rules/cargo`, and packs never collide with local rules or with each other.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::config::PreRule;
+use crate::error::ConfigIOError;
+
+/// Separates the namespace of a pack from names of its rules.
+pub const NAMESPACE_SEPARATOR: char = '/';
+
+/// A file included by a config.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Pack {
+    /// Namespace of rules in this pack. Defaults to the file name without extension.
+    #[serde(default)]
+    namespace: Option<String>,
+    /// Other packs to include, relative to this pack.
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    rules: HashMap<String, PreRule>,
+}
+
+/// Load rules from packs listed in `include`, relative to the config file at `path`.
+///
+/// Packs are applied in order, so later packs win over earlier ones, and a pack wins over the
+/// packs it includes.
+///
+/// # Errors
+/// Returns error if some pack can't be read or parsed, or some pack includes itself.
+pub fn resolve(include: &[String], path: &Path) -> Result<HashMap<String, PreRule>, ConfigIOError> {
+    let mut rules = HashMap::new();
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    dfs_include(&mut rules, include, &path, &mut vec![path.clone()])?;
+    Ok(rules)
+}
+
+fn dfs_include(
+    rules: &mut HashMap<String, PreRule>,
+    include: &[String],
+    from: &Path,
+    visiting: &mut Vec<PathBuf>,
+) -> Result<(), ConfigIOError> {
+    let base = from.parent().unwrap_or(from);
+    for entry in include {
+        let wrap = |source| ConfigIOError::Include {
+            path: entry.clone(),
+            source: Box::new(source),
+        };
+        let path = base
+            .join(shellexpand::tilde(entry).as_ref())
+            .canonicalize()
+            .map_err(|e| wrap(ConfigIOError::ReadConfig(e)))?;
+        if visiting.contains(&path) {
+            return Err(wrap(ConfigIOError::IncludeLoop(path)));
+        }
+        let content = fs::read_to_string(&path).map_err(|e| wrap(ConfigIOError::ReadConfig(e)))?;
+        let pack: Pack = serde_yaml::from_str(&content)
+            .map_err(|e| wrap(ConfigIOError::Deserialize(Box::new(e))))?;
+
+        visiting.push(path.clone());
+        dfs_include(rules, &pack.include, &path, visiting).map_err(wrap)?;
+        visiting.pop();
+
+        let namespace = pack.namespace.clone().unwrap_or_else(|| {
+            path.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        });
+        let qualify = |name: &str| format!("{namespace}{NAMESPACE_SEPARATOR}{name}");
+        for (name, rule) in &pack.rules {
+            let rule = match rule {
+                PreRule::Concrete(rule) => PreRule::Concrete(rule.clone()),
+                // References to rules in the same pack are namespaced too.
+                PreRule::Union(children) => PreRule::Union(
+                    children
+                        .iter()
+                        .map(|child| {
+                            if pack.rules.contains_key(child) {
+                                qualify(child)
+                            } else {
+                                child.clone()
+                            }
+                        })
+                        .collect(),
+                ),
+            };
+            rules.insert(qualify(name), rule);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::config::PreRule;
+    use crate::error::ConfigIOError;
+    use crate::include::resolve;
+
+    #[test]
+    fn must_resolve_includes() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = |name: &str| temp_dir.path().join(name);
+        fs::create_dir(path("packs")).unwrap();
+        fs::write(
+            path("packs/rust.yaml"),
+            "include: [ common.yaml ]\nrules:\n  cargo: { excludes: [ target ] }\n  all: [ cargo, common/vcs ]\n",
+        )
+        .unwrap();
+        fs::write(
+            path("packs/common.yaml"),
+            "rules:\n  vcs: { excludes: [ .git ] }\n  cargo: { excludes: [ never ] }\n",
+        )
+        .unwrap();
+        fs::write(
+            path("packs/override.yaml"),
+            "namespace: rust\nrules:\n  cargo: { excludes: [ target, out ] }\n",
+        )
+        .unwrap();
+
+        let include = vec![
+            String::from("packs/rust.yaml"),
+            String::from("packs/override.yaml"),
+        ];
+        let rules = resolve(&include, &path("config.yaml")).unwrap();
+        let mut names: Vec<_> = rules.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            ["common/cargo", "common/vcs", "rust/all", "rust/cargo"]
+        );
+        assert!(matches!(
+            &rules["rust/all"],
+            PreRule::Union(children) if children == &["rust/cargo", "common/vcs"]
+        ));
+        // Later packs win.
+        assert!(matches!(
+            &rules["rust/cargo"],
+            PreRule::Concrete(rule) if rule.excludes.len() == 2
+        ));
+    }
+
+    #[test]
+    fn must_detect_include_loop() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = |name: &str| temp_dir.path().join(name);
+        fs::write(path("a.yaml"), "include: [ b.yaml ]\n").unwrap();
+        fs::write(path("b.yaml"), "include: [ a.yaml ]\n").unwrap();
+        fs::write(path("config.yaml"), "include: [ a.yaml ]\n").unwrap();
+
+        let include = vec![String::from("a.yaml")];
+        let mut e = resolve(&include, &path("config.yaml")).unwrap_err();
+        while let ConfigIOError::Include { source, .. } = e {
+            e = *source;
+        }
+        assert!(matches!(e, ConfigIOError::IncludeLoop(_)));
+
+        // Including the same pack twice is not a loop.
+        fs::write(path("b.yaml"), "rules: { x: [] }\n").unwrap();
+        let include = vec![String::from("a.yaml"), String::from("b.yaml")];
+        assert!(resolve(&include, &path("config.yaml")).is_ok());
+
+        let include = vec![String::from("missing.yaml")];
+        assert!(matches!(
+            resolve(&include, &path("config.yaml")),
+            Err(ConfigIOError::Include { path, .. }) if path == "missing.yaml"
+        ));
+    }
+}
@@ -1,20 +1,39 @@
 #![allow(clippy::module_name_repetitions, clippy::default_trait_access)]
 
-pub use config::{ConfigManager, PreConfig};
+pub use backend::{BackendKind, ExcludeState, ExclusionBackend, Memory};
+pub use config::{Config, ConfigManager, PreConfig, RuleId, RuleVerdict};
 pub use error::{ApplyError, ApplyErrors, ConfigError};
+pub use event::{Event, EventSink, NoopSink};
 pub use metrics::Metrics;
 pub use mission::{Mission, ScanStatus};
 pub use properties::Store;
-pub use tmutil::ExclusionActionBatch;
-pub use walker::{walk_non_recursive, walk_recursive};
-pub use watcher::watch_task;
+pub use skip_cache::SkipCache;
+pub use source_map::Location;
+pub use tmutil::{ExclusionAction, ExclusionActionBatch};
+pub use validate::{validate, validate_source, Diagnostic, Severity};
+pub use walker::{
+    explain, walk_non_recursive, walk_recursive, walk_subtree, Explanation, RuleExplanation,
+};
+pub use watcher::{watch, watch_task};
 
+mod age;
+mod backend;
 mod config;
 mod error;
+mod event;
+mod gitignore;
+mod include;
 mod metrics;
+mod migration;
 mod mission;
+mod overrides;
+mod patch;
+mod pattern;
 mod properties;
+mod size;
 mod skip_cache;
+mod source_map;
 mod tmutil;
+mod validate;
 mod walker;
 mod watcher;
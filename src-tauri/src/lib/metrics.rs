@@ -7,6 +7,8 @@ use arc_swap::ArcSwap;
 use serde::{Serialize, Serializer};
 use ts_rs::TS;
 
+use crate::config::RuleId;
+
 #[derive(Serialize, TS)]
 #[ts(export, export_to = "../src/bindings/")]
 #[serde(rename_all = "kebab-case")]
@@ -20,6 +22,9 @@ pub struct Metrics {
     #[ts(type = "string")]
     #[serde(serialize_with = "serialize_arc_path")]
     last_excluded: ArcSwap<Box<Path>>,
+    #[ts(type = "string")]
+    #[serde(serialize_with = "serialize_arc_string")]
+    last_excluded_rule: ArcSwap<String>,
     #[ts(type = "number")]
     #[serde(serialize_with = "serialize_atomic_u64")]
     last_excluded_time: AtomicU64,
@@ -31,6 +36,7 @@ impl Default for Metrics {
             files_excluded: AtomicUsize::new(0),
             files_included: AtomicUsize::new(0),
             last_excluded: ArcSwap::new(Arc::new(Box::from(Path::new("")))),
+            last_excluded_rule: ArcSwap::new(Arc::new(String::new())),
             last_excluded_time: AtomicU64::new(0),
         }
     }
@@ -43,12 +49,14 @@ impl Metrics {
     pub fn inc_included(&self, n: usize) {
         self.files_included.fetch_add(n, Ordering::Relaxed);
     }
-    pub fn set_last_excluded(&self, path: &Path) {
+    pub fn set_last_excluded(&self, path: &Path, rule: Option<&RuleId>) {
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .expect("past is future")
             .as_secs();
         self.last_excluded.store(Arc::new(Box::from(path)));
+        self.last_excluded_rule
+            .store(Arc::new(rule.map(ToString::to_string).unwrap_or_default()));
         self.last_excluded_time.store(now, Ordering::Relaxed);
     }
 }
@@ -73,3 +81,10 @@ where
 {
     t.load().serialize(s)
 }
+
+fn serialize_arc_string<S>(t: &ArcSwap<String>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    t.load().serialize(s)
+}
@@ -0,0 +1,105 @@
+//! Migrations of config files written by older versions.
+//!
+//! Migrations work on the raw YAML document before it's deserialized, so that shapes no longer
+//! understood by [`PreConfig`](crate::config::PreConfig) can still be read. Each migration upgrades
+//! a config by exactly one version.
+use serde_yaml::{Mapping, Value};
+
+use crate::error::ConfigIOError;
+
+/// Version of configs written by this build.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Name of the version key.
+pub const VERSION_KEY: &str = "version";
+
+/// `MIGRATIONS[i]` upgrades a config from version `i` to `i + 1`.
+const MIGRATIONS: [fn(&mut Mapping); CURRENT_VERSION as usize] = [v0_to_v1];
+
+/// Configs written before versioning was introduced.
+///
+/// Unversioned configs share the shape of version 1, so only the version is stamped.
+fn v0_to_v1(_config: &mut Mapping) {}
+
+/// Upgrade a config document to [`CURRENT_VERSION`] in place.
+///
+/// Configs without a `version` key are considered version 0. Returns the original version.
+///
+/// # Errors
+/// Returns error if the version is invalid, or newer than [`CURRENT_VERSION`].
+pub fn migrate(config: &mut Value) -> Result<u32, ConfigIOError> {
+    // Let deserialization report non-mapping documents.
+    let Value::Mapping(mapping) = config else {
+        return Ok(CURRENT_VERSION);
+    };
+    let version = match mapping.get(VERSION_KEY) {
+        None => 0,
+        Some(version) => serde_yaml::from_value(version.clone())
+            .map_err(|e| ConfigIOError::Deserialize(Box::new(e)))?,
+    };
+    if version > CURRENT_VERSION {
+        return Err(ConfigIOError::UnsupportedVersion(version));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(mapping);
+    }
+    mapping.insert(VERSION_KEY.into(), CURRENT_VERSION.into());
+    Ok(version)
+}
+
+/// Whether migrating `original` into `migrated` changed nothing but the version.
+///
+/// If so, the migrated config can still be located in the source of the original one.
+pub(crate) fn only_version_changed(original: Value, migrated: Value) -> bool {
+    fn without_version(mut config: Value) -> Value {
+        if let Some(mapping) = config.as_mapping_mut() {
+            mapping.remove(VERSION_KEY);
+        }
+        config
+    }
+    without_version(original) == without_version(migrated)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::error::ConfigIOError;
+    use crate::migration::{migrate, CURRENT_VERSION};
+
+    fn version(config: &Value) -> Option<u64> {
+        config.get("version").and_then(Value::as_u64)
+    }
+
+    #[test]
+    fn must_migrate_unversioned() {
+        let mut config: Value = serde_yaml::from_str("no-include: true\n").unwrap();
+        assert_eq!(migrate(&mut config).unwrap(), 0);
+        assert_eq!(version(&config), Some(u64::from(CURRENT_VERSION)));
+        assert_eq!(config.get("no-include"), Some(&Value::Bool(true)));
+    }
+
+    #[test]
+    fn must_keep_current() {
+        let mut config: Value =
+            serde_yaml::from_str(&format!("version: {CURRENT_VERSION}\n")).unwrap();
+        assert_eq!(migrate(&mut config).unwrap(), CURRENT_VERSION);
+        assert_eq!(version(&config), Some(u64::from(CURRENT_VERSION)));
+    }
+
+    #[test]
+    fn must_reject_invalid_version() {
+        let mut config: Value =
+            serde_yaml::from_str(&format!("version: {}\n", CURRENT_VERSION + 1)).unwrap();
+        assert!(matches!(
+            migrate(&mut config),
+            Err(ConfigIOError::UnsupportedVersion(_))
+        ));
+
+        let mut config: Value = serde_yaml::from_str("version: latest\n").unwrap();
+        assert!(matches!(
+            migrate(&mut config),
+            Err(ConfigIOError::Deserialize(_))
+        ));
+    }
+}
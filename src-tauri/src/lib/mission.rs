@@ -1,6 +1,6 @@
 #![allow(clippy::use_self)]
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::{io, mem};
@@ -9,21 +9,26 @@ use arc_swap::ArcSwap;
 use parking_lot::{Mutex, RwLock};
 use serde::Serialize;
 use serde_json::Value;
-use tauri::async_runtime::{channel, JoinHandle};
-use tauri::{AppHandle, Manager};
-use tracing::error;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::channel;
+use tokio::task::JoinHandle;
+use tracing::warn;
 use ts_rs::TS;
 
+use crate::backend::ExclusionBackend;
 use crate::config::{Config, ConfigManager, PreConfig};
 use crate::error::ConfigError;
+use crate::event::{Event, EventSink};
 use crate::metrics::Metrics;
 use crate::properties::Store;
 use crate::tmutil::ExclusionActionBatch;
-use crate::walker::walk_recursive;
-use crate::watcher::watch_task;
+use crate::validate::{validate, Diagnostic};
+use crate::walker::{explain, walk_recursive, Explanation};
+use crate::watcher::{watch_config_task, watch_task};
 
 pub struct Mission {
-    app: AppHandle,
+    runtime: Handle,
+    events: Box<dyn EventSink>,
     properties: Store,
     config_manager: ConfigManager,
     pre_config: ArcSwap<PreConfig>,
@@ -62,29 +67,30 @@ pub enum ScanStatus {
 impl Mission {
     /// Create a new mission.
     ///
-    /// This function will start a watcher task.
+    /// This function will start a watcher task. Tasks of the mission are spawned on the tokio
+    /// runtime it's created in.
     ///
     /// # Errors
-    /// Returns error if can't load config.
+    /// Returns error if can't load config, e.g. when it's written by a newer version.
+    ///
+    /// # Panics
+    /// Panics if called outside a tokio runtime.
     pub fn new_arc(
-        app: AppHandle,
+        events: impl EventSink + 'static,
         config_manager: ConfigManager,
         properties: Store,
     ) -> Result<Arc<Self>, ConfigError> {
-        let pre_config = match config_manager.load() {
-            Ok(pre_config) => pre_config,
-            Err(e) => {
-                error!(?e, "Failed to load config, resetting to default");
-                config_manager.reset()?;
-                config_manager.load()?
-            }
-        };
+        let (pre_config, _) = config_manager.load_or_reset()?;
         let config = Config::try_from(pre_config.clone())?;
+        let runtime = Handle::current();
         Ok(Arc::new_cyclic(move |this| {
             let task = watch_task(this.clone());
-            let handle = tauri::async_runtime::spawn(task);
+            let handle = runtime.spawn(task);
+            // The config watcher stops by itself once the mission is dropped.
+            drop(runtime.spawn(watch_config_task(this.clone())));
             Self {
-                app,
+                runtime,
+                events: Box::new(events),
                 properties,
                 config_manager,
                 pre_config: ArcSwap::from_pointee(pre_config),
@@ -100,10 +106,10 @@ impl Mission {
         self.properties.get(key)
     }
     pub fn store_set(&self, key: String, value: Value) {
-        self.properties.set(&self.app, key, value);
+        self.properties.set(&*self.events, key, value);
     }
     pub fn store_del(&self, key: &str) {
-        self.properties.del(&self.app, key);
+        self.properties.del(&*self.events, key);
     }
     /// Get internal config.
     pub(crate) fn config_(&self) -> Arc<Config> {
@@ -113,17 +119,46 @@ impl Mission {
     pub fn config(&self) -> Arc<PreConfig> {
         self.pre_config.load().clone()
     }
+    /// Get current backend.
+    pub fn backend(&self) -> Arc<dyn ExclusionBackend> {
+        self.config.load().backend.clone()
+    }
+    /// Path to the config file.
+    pub fn config_path(&self) -> &Path {
+        self.config_manager.path()
+    }
     /// Get metrics.
     pub fn metrics(&self) -> Arc<Metrics> {
         self.metrics.clone()
     }
+    /// Explain why a path is or isn't excluded with current config.
+    ///
+    /// # Errors
+    /// Returns error if the path can't be canonicalized, e.g. it doesn't exist.
+    pub fn explain(&self, path: &Path) -> io::Result<Explanation> {
+        let path = path.canonicalize()?;
+        let config = self.config_();
+        Ok(explain(&path, &config.walk, &*config.backend))
+    }
+    /// Validate a config, along with rule packs it includes.
+    pub fn validate_config(&self, config: &mut PreConfig) -> Vec<Diagnostic> {
+        match config.resolve_includes(self.config_manager.path()) {
+            Ok(()) => validate(config, None),
+            Err(e) => vec![Diagnostic::error(&e)],
+        }
+    }
     /// Set new config.
     ///
     /// This method will restart watcher task.
     ///
     /// # Errors
-    /// Returns error if can't persist config, or can't parse surface config (shouldn't happen).
-    pub fn set_config(self: Arc<Self>, config: PreConfig) -> Result<(), ConfigError> {
+    /// Returns [`ConfigError::Invalid`] with all problems found if the config is invalid, or error
+    /// if can't persist config.
+    pub fn set_config(self: Arc<Self>, mut config: PreConfig) -> Result<(), ConfigError> {
+        let diagnostics = self.validate_config(&mut config);
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            return Err(ConfigError::Invalid(diagnostics));
+        }
         let config_ = Config::try_from(config.clone())?;
         self.config_manager.save(&config)?;
         self.pre_config.store(Arc::new(config));
@@ -131,26 +166,63 @@ impl Mission {
         self.reload();
         Ok(())
     }
+    /// Reload config from the config file, after it's edited on disk.
+    ///
+    /// Nothing happens if the file is equivalent to current config, e.g. when it's just saved by
+    /// [`Mission::set_config`]. If the file can't be loaded, current config and watcher task are
+    /// kept, and the problems are broadcast as [`Event::ConfigReloadFailed`].
+    ///
+    /// # Errors
+    /// Returns [`ConfigError::Invalid`] with all problems found if the config file is invalid, or
+    /// error if can't read it.
+    pub fn reload_config_file(self: Arc<Self>) -> Result<(), ConfigError> {
+        match self.load_config_file() {
+            Ok(Some((config, config_))) => {
+                self.pre_config.store(Arc::new(config));
+                self.config.store(Arc::new(config_));
+                self.reload();
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(e) => {
+                let diagnostics = match &e {
+                    ConfigError::Invalid(diagnostics) => diagnostics.clone(),
+                    e => vec![Diagnostic::error(e)],
+                };
+                self.events.emit(Event::ConfigReloadFailed(diagnostics));
+                Err(e)
+            }
+        }
+    }
+    /// Load and validate the config file, or `None` if it's equivalent to current config.
+    fn load_config_file(&self) -> Result<Option<(PreConfig, Config)>, ConfigError> {
+        let diagnostics = self.config_manager.validate()?;
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            return Err(ConfigError::Invalid(diagnostics));
+        }
+        let config = self.config_manager.load()?;
+        if config == **self.pre_config.load() {
+            return Ok(None);
+        }
+        let config_ = Config::try_from(config.clone())?;
+        Ok(Some((config, config_)))
+    }
     /// Reload watcher task to apply new config.
     pub fn reload(self: Arc<Self>) {
         // Create and spawn new watch task.
         let new_task = watch_task(Arc::downgrade(&self));
-        let handle = tauri::async_runtime::spawn(new_task);
+        let handle = self.runtime.spawn(new_task);
 
         // Stop old watch task.
         let old_handle = mem::replace(&mut *self.watcher_handle.lock(), handle);
         old_handle.abort();
 
         // Broadcast new config.
-        self.app
-            .emit_all("config_changed", self.config())
-            .expect("failed to broadcast event");
+        self.events.emit(Event::ConfigChanged(self.config()));
     }
     fn set_scan_status(&self, status: ScanStatus) {
         *self.scan_status.write() = status.clone();
-        self.app
-            .emit_all("scan_status_changed", status)
-            .expect("failed to broadcast event");
+        self.events.emit(Event::ScanStatusChanged(status));
     }
     pub fn stop_full_scan(&self) {
         if let Some(handle) = self.scan_handle.lock().take() {
@@ -178,14 +250,14 @@ impl Mission {
                     move || {
                         let config = this.config_();
                         let walk_config = (*config.walk).clone();
-                        let support_dump = config.support_dump;
-                        let result = walk_recursive(
-                            walk_config,
-                            support_dump,
-                            curr_tx,
-                            found,
-                            abort.clone(),
-                        );
+                        let backend = config.backend.clone();
+                        let on_progress = move |path: &Path| {
+                            if let Err(e) = curr_tx.try_send(path.to_path_buf()) {
+                                warn!("Failed to send current path: {}", e);
+                            }
+                        };
+                        let result =
+                            walk_recursive(walk_config, backend, on_progress, found, abort.clone());
                         if !abort.load(Ordering::Relaxed) {
                             this.set_scan_status(ScanStatus::Result(result));
                         }
@@ -203,8 +275,118 @@ impl Mission {
 
         let handle = ScanHandle {
             abort_flag: abort,
-            task_handle: tauri::async_runtime::spawn(scan_task),
+            task_handle: self.runtime.spawn(scan_task),
         };
         self.scan_handle.lock().replace(handle);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::time::Duration;
+
+    use crossbeam::channel::unbounded;
+    use tempfile::TempDir;
+    use tokio::runtime::Runtime;
+
+    use crate::config::ConfigManager;
+    use crate::error::ConfigError;
+    use crate::event::Event;
+    use crate::mission::{Mission, ScanStatus};
+    use crate::properties::Store;
+
+    #[test]
+    fn must_emit_events_to_sink() {
+        let temp = TempDir::new().unwrap();
+        let config_path = temp.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            "backends: [cachedir-tag]\ndirectories: []\nskips: []\nrules: {}\n",
+        )
+        .unwrap();
+
+        let runtime = Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let (tx, rx) = unbounded();
+        let mission = Mission::new_arc(
+            tx,
+            ConfigManager::with_path(config_path),
+            Store::new(temp.path()),
+        )
+        .unwrap();
+
+        mission
+            .clone()
+            .set_config((*mission.config()).clone())
+            .unwrap();
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Event::ConfigChanged(_)
+        ));
+
+        mission.store_set("key".to_string(), "value".into());
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Event::PropertiesChanged(props) if props["key"] == "value"
+        ));
+
+        mission.clone().full_scan();
+        loop {
+            match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+                Event::ScanStatusChanged(ScanStatus::Result(batch)) => {
+                    assert!(batch.is_empty());
+                    break;
+                }
+                Event::ScanStatusChanged(_) => {}
+                event => panic!("unexpected event: {:?}", event),
+            }
+        }
+    }
+
+    #[test]
+    fn must_reload_config_file() {
+        let temp = TempDir::new().unwrap();
+        let config_path = temp.path().join("config.yaml");
+        let content = "backends: [cachedir-tag]\ndirectories: []\nskips: []\nrules: {}\n";
+        fs::write(&config_path, content).unwrap();
+
+        let runtime = Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let (tx, rx) = unbounded();
+        let mission = Mission::new_arc(
+            tx,
+            ConfigManager::with_path(config_path.clone()),
+            Store::new(temp.path()),
+        )
+        .unwrap();
+
+        // Unchanged config is not reloaded.
+        mission.clone().reload_config_file().unwrap();
+        assert!(rx.try_recv().is_err());
+
+        fs::write(&config_path, format!("{content}no-include: true\n")).unwrap();
+        mission.clone().reload_config_file().unwrap();
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Event::ConfigChanged(config) if config.no_include
+        ));
+
+        // Invalid config is reported, and current config is kept.
+        fs::write(
+            &config_path,
+            "backends: [cachedir-tag]\ndirectories: []\nskips: []\nrules: { a: [ b ] }\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            mission.clone().reload_config_file(),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Event::ConfigReloadFailed(diagnostics)
+                if diagnostics.iter().any(|d| d.message == "Missing rule: b")
+        ));
+        assert!(mission.config().no_include);
+    }
+}
@@ -0,0 +1,175 @@
+//! Per-directory override files.
+//!
+//! A `.tmexclude.yaml` file may be shipped in any directory to declare extra excludes, extra rules
+//! or forced includes for that directory and all its descendants. Overrides are layered on top of
+//! the rules in the main config.
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use moka::sync::Cache;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::config::{CompiledRule, Directory, Protect, Rule};
+use crate::error::ConfigError;
+
+/// Name of override files.
+pub const OVERRIDE_FILE: &str = ".tmexclude.yaml";
+
+/// Content of an override file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct PreOverride {
+    /// Paths to be excluded unconditionally.
+    #[serde(default)]
+    excludes: Vec<PathBuf>,
+    /// Paths never to be excluded, even if some rules match.
+    #[serde(default)]
+    includes: Vec<PathBuf>,
+    /// Extra rules.
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+impl PreOverride {
+    fn compile(self, dir: &Path, file: &Path) -> Result<Directory, ConfigError> {
+        let name = |suffix: &str| format!("{}#{}", file.display(), suffix);
+        let mut rules = vec![];
+        if !self.excludes.is_empty() {
+            let rule = Rule {
+                excludes: self.excludes,
+                ..Default::default()
+            };
+            rules.push(Arc::new(CompiledRule::new(&name("excludes"), rule)?));
+        }
+        for (i, rule) in self.rules.into_iter().enumerate() {
+            rules.push(Arc::new(CompiledRule::new(&name(&i.to_string()), rule)?));
+        }
+        Ok(Directory {
+            path: dir.to_path_buf(),
+            rules,
+            from_gitignore: None,
+            protect: Arc::new(Protect::new(&name("includes"), self.includes)?),
+        })
+    }
+}
+
+/// Load the override file in given directory, if any.
+///
+/// Invalid override files are ignored with a warning.
+#[must_use]
+pub fn load(dir: &Path) -> Option<Directory> {
+    let file = dir.join(OVERRIDE_FILE);
+    let content = match fs::read_to_string(&file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Error when reading override file {:?}: {}", file, e);
+            return None;
+        }
+    };
+    serde_yaml::from_str::<PreOverride>(&content)
+        .map_err(|e| ConfigError::Deserialize(Box::new(e)))
+        .and_then(|pre_override| pre_override.compile(dir, &file))
+        .map_err(|e| warn!("Invalid override file {:?}: {}", file, e))
+        .ok()
+}
+
+/// Load override files in `root` and its ancestors covered by `directories`.
+///
+/// `root` itself is included if `include_root` is set.
+#[must_use]
+pub fn load_ancestors(
+    root: &Path,
+    directories: &[Directory],
+    include_root: bool,
+) -> Vec<Directory> {
+    root.ancestors()
+        .skip(usize::from(!include_root))
+        .take_while(|dir| {
+            directories
+                .iter()
+                .any(|directory| dir.starts_with(&directory.path))
+        })
+        .filter_map(load)
+        .collect()
+}
+
+const TRACKER_MAX_CAPACITY: u64 = 4096;
+
+/// Tracks modification time of override files to tell when they change.
+#[derive(Clone)]
+pub struct OverrideTracker(Arc<Cache<PathBuf, Option<SystemTime>>>);
+
+impl Default for OverrideTracker {
+    fn default() -> Self {
+        Self(Arc::new(Cache::new(TRACKER_MAX_CAPACITY)))
+    }
+}
+
+impl OverrideTracker {
+    /// Check if the override file in given directory has been created, modified or removed since
+    /// the last check.
+    ///
+    /// Directories checked for the first time are considered changed only if they contain an
+    /// override file.
+    pub fn changed(&self, dir: &Path) -> bool {
+        let mtime = fs::metadata(dir.join(OVERRIDE_FILE))
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let last = self.0.get(dir);
+        self.0.insert(dir.to_path_buf(), mtime);
+        match last {
+            Some(last) => last != mtime,
+            None => mtime.is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use tempfile::TempDir;
+
+    use crate::overrides::{load, OverrideTracker, OVERRIDE_FILE};
+
+    #[test]
+    fn must_load_override() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        assert!(load(dir).is_none());
+
+        fs::write(
+            dir.join(OVERRIDE_FILE),
+            "excludes: [ out ]\nincludes: [ build ]\nrules:\n  - excludes: [ tmp ]\n    if-exists: [ Makefile ]\n",
+        )
+        .unwrap();
+        let directory = load(dir).unwrap();
+        assert_eq!(directory.path, dir);
+        assert_eq!(directory.rules.len(), 2);
+        assert!(directory.protect.is_protected(dir, Path::new("build")));
+
+        fs::write(dir.join(OVERRIDE_FILE), "unknown: true\n").unwrap();
+        assert!(load(dir).is_none());
+    }
+
+    #[test]
+    fn must_track_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let tracker = OverrideTracker::default();
+
+        assert!(!tracker.changed(dir));
+        fs::write(dir.join(OVERRIDE_FILE), "excludes: [ out ]\n").unwrap();
+        assert!(tracker.changed(dir));
+        assert!(!tracker.changed(dir));
+        fs::remove_file(dir.join(OVERRIDE_FILE)).unwrap();
+        assert!(tracker.changed(dir));
+        assert!(!tracker.changed(dir));
+    }
+}
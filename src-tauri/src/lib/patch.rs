@@ -0,0 +1,455 @@
+//! Patching YAML documents in place.
+//!
+//! Re-serializing a config drops its comments and reorders its keys, so configs are saved by
+//! patching only the nodes that changed. Everything else, comments included, is kept as written.
+use serde_yaml::{Mapping, Value};
+
+use crate::source_map::{Node, Segment, SourceMap, Style};
+
+/// Patch `source` so that it reads as `new` instead of `old`.
+///
+/// `old` must be what `source` reads as. Values not in the source yet are passed to `prune` along
+/// with their paths before being inserted, so that entries filled in by default can be dropped.
+/// Returns `None` if the document can't be patched in place, e.g. its root is not a block mapping.
+#[must_use]
+pub fn patch(
+    source: &str,
+    old: &Value,
+    new: &Value,
+    prune: &dyn Fn(&[Segment], &mut Value),
+) -> Option<String> {
+    let map = SourceMap::new(source)?;
+    let (Value::Mapping(old), Value::Mapping(new)) = (old, new) else {
+        return None;
+    };
+    let root = map.get(&[])?;
+    if root.style != Style::BlockMapping {
+        return None;
+    }
+    let mut patcher = Patcher {
+        source,
+        map: &map,
+        prune,
+        edits: vec![],
+    };
+    patcher.mapping(&mut vec![], root, old, new)?;
+    patcher.apply()
+}
+
+/// Replace `start..end` of the source with `text`.
+struct Edit {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+struct Patcher<'a> {
+    source: &'a str,
+    map: &'a SourceMap,
+    prune: &'a dyn Fn(&[Segment], &mut Value),
+    edits: Vec<Edit>,
+}
+
+impl Patcher<'_> {
+    fn apply(mut self) -> Option<String> {
+        // Insertions go before removals starting at the same offset.
+        self.edits.sort_by_key(|edit| (edit.start, edit.end));
+        let mut patched = String::with_capacity(self.source.len());
+        let mut cursor = 0;
+        for edit in self.edits {
+            if edit.start < cursor {
+                return None;
+            }
+            patched.push_str(&self.source[cursor..edit.start]);
+            patched.push_str(&edit.text);
+            cursor = edit.end;
+        }
+        patched.push_str(&self.source[cursor..]);
+        Some(patched)
+    }
+    fn edit(&mut self, start: usize, end: usize, text: String) {
+        self.edits.push(Edit { start, end, text });
+    }
+    /// Patch the node at `path`, which exists in the source.
+    ///
+    /// Collections that can't be patched entry by entry are replaced as a whole.
+    fn value(&mut self, path: &mut Vec<Segment>, old: &Value, new: &Value) -> Option<()> {
+        if old == new {
+            return Some(());
+        }
+        let node = self.map.get(path)?;
+        let checkpoint = self.edits.len();
+        let patched = match (node.style, old, new) {
+            (Style::BlockMapping, Value::Mapping(old), Value::Mapping(new)) if !new.is_empty() => {
+                self.mapping(path, node, old, new)
+            }
+            (Style::BlockSequence, Value::Sequence(old), Value::Sequence(new))
+                if !new.is_empty() =>
+            {
+                self.sequence(path, node, old, new)
+            }
+            _ => None,
+        };
+        if patched.is_none() {
+            self.edits.truncate(checkpoint);
+            if node.style == Style::Empty {
+                return None;
+            }
+            self.edit(node.start, node.end, flow(new)?);
+        }
+        Some(())
+    }
+    fn mapping(
+        &mut self,
+        path: &mut Vec<Segment>,
+        node: &Node,
+        old: &Mapping,
+        new: &Mapping,
+    ) -> Option<()> {
+        let indent = node.location.column - 1;
+        // Keys written in the source, in the order of `new`.
+        let mut written: Vec<&str> = vec![];
+        for (key, value) in new {
+            let key = key.as_str()?;
+            path.push(Segment::from(key));
+            let entry = self.map.get(path);
+            match (old.get(key), entry) {
+                (Some(old), Some(entry)) if entry.style == Style::Empty => {
+                    if old != value {
+                        // Write the value right after the colon.
+                        let key_end = self.map.get_key(path)?.end;
+                        let colon = key_end + self.source[key_end..].find(':')? + 1;
+                        self.edit(colon, colon, format!(" {}", flow(value)?));
+                    }
+                }
+                (Some(old), Some(_)) => self.value(path, old, value)?,
+                // Not what the source reads as.
+                (None, Some(_)) => return None,
+                // Omitted from the source, and still the default.
+                (Some(old), None) if old == value => {}
+                (_, None) => {
+                    let at = match written.last() {
+                        Some(prev) => {
+                            let prev = self.entry_span(path, prev)?;
+                            self.line_end(prev.1)
+                        }
+                        None => self.first_entry_start(path, new, node)?,
+                    };
+                    let mut value = value.clone();
+                    (self.prune)(path, &mut value);
+                    let mut entry = Mapping::new();
+                    entry.insert(key.into(), value);
+                    let text = self.block(&Value::Mapping(entry), indent, at)?;
+                    self.edit(at, at, text);
+                }
+            }
+            if self.map.get(path).is_some() {
+                written.push(key);
+            }
+            path.pop();
+        }
+        for key in old.keys() {
+            let key = key.as_str()?;
+            if new.contains_key(key) {
+                continue;
+            }
+            path.push(Segment::from(key));
+            if self.map.get(path).is_some() {
+                let (start, end) = self.entry_span(path, key)?;
+                let line_start = self.whole_line_start(start)?;
+                self.edit(line_start, self.line_end(end), String::new());
+            }
+            path.pop();
+        }
+        Some(())
+    }
+    fn sequence(
+        &mut self,
+        path: &mut Vec<Segment>,
+        node: &Node,
+        old: &[Value],
+        new: &[Value],
+    ) -> Option<()> {
+        let indent = node.location.column - 1;
+        // Items equal in both, as pairs of indices, followed by a sentinel.
+        let mut anchors = lcs(old, new);
+        anchors.push((old.len(), new.len()));
+        let (mut i, mut j) = (0, 0);
+        for (next_i, next_j) in anchors {
+            // Patch items in place pairwise, then remove or insert what's left.
+            let paired = (next_i - i).min(next_j - j);
+            for k in 0..paired {
+                path.push(Segment::from(i + k));
+                self.value(path, &old[i + k], &new[j + k])?;
+                path.pop();
+            }
+            for k in i + paired..next_i {
+                path.push(Segment::from(k));
+                let start = self.dash(self.map.get(path)?.start)?;
+                let end = self.map.get(path)?.end;
+                self.edit(
+                    self.whole_line_start(start)?,
+                    self.line_end(end),
+                    String::new(),
+                );
+                path.pop();
+            }
+            if j + paired < next_j {
+                let at = if next_i < old.len() {
+                    path.push(Segment::from(next_i));
+                    let start = self.dash(self.map.get(path)?.start)?;
+                    path.pop();
+                    self.whole_line_start(start)?
+                } else {
+                    self.line_end(node.end)
+                };
+                let items = (j + paired..next_j)
+                    .map(|k| {
+                        let mut item = new[k].clone();
+                        path.push(Segment::from(k));
+                        (self.prune)(path, &mut item);
+                        path.pop();
+                        item
+                    })
+                    .collect();
+                let items = Value::Sequence(items);
+                let text = self.block(&items, indent, at)?;
+                self.edit(at, at, text);
+            }
+            (i, j) = (next_i + 1, next_j + 1);
+        }
+        Some(())
+    }
+    /// Span of the entry with `key` in the mapping containing `path`.
+    fn entry_span(&self, path: &[Segment], key: &str) -> Option<(usize, usize)> {
+        let mut path = path.to_vec();
+        *path.last_mut()? = Segment::from(key);
+        let start = self.map.get_key(&path)?.start;
+        let value = self.map.get(&path)?;
+        let end = if value.style == Style::Empty {
+            self.map.get_key(&path)?.end
+        } else {
+            value.end
+        };
+        Some((start, end))
+    }
+    /// Where to insert an entry before all entries written in the source.
+    fn first_entry_start(&self, path: &[Segment], new: &Mapping, node: &Node) -> Option<usize> {
+        let first = new
+            .keys()
+            .filter_map(Value::as_str)
+            .filter_map(|key| self.entry_span(path, key))
+            .map(|(start, _)| start)
+            .min();
+        match first {
+            Some(start) => self.whole_line_start(start),
+            None => Some(self.line_end(node.end)),
+        }
+    }
+    /// Start of the line `offset` is on, if there's only indentation before it.
+    fn whole_line_start(&self, offset: usize) -> Option<usize> {
+        let line_start = self.source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        self.source[line_start..offset]
+            .chars()
+            .all(|c| c == ' ')
+            .then_some(line_start)
+    }
+    /// Start of the line after the one `offset` is on.
+    fn line_end(&self, offset: usize) -> usize {
+        self.source[offset..]
+            .find('\n')
+            .map_or(self.source.len(), |i| offset + i + 1)
+    }
+    /// The dash of a block sequence item starting at `offset`.
+    fn dash(&self, offset: usize) -> Option<usize> {
+        let before = self.source[..offset].trim_end_matches(' ');
+        before.ends_with('-').then(|| before.len() - 1)
+    }
+    /// `value` in block style, indented by `indent` and to be inserted at `at`.
+    fn block(&self, value: &Value, indent: usize, at: usize) -> Option<String> {
+        let yaml = serde_yaml::to_string(value).ok()?;
+        let mut text: String = yaml
+            .lines()
+            .map(|line| format!("{:indent$}{line}\n", ""))
+            .collect();
+        if at == self.source.len() && !self.source.is_empty() && !self.source.ends_with('\n') {
+            text.insert(0, '\n');
+        }
+        Some(text)
+    }
+}
+
+/// Pairs of indices of a longest common subsequence of `a` and `b`.
+fn lcs(a: &[Value], b: &[Value]) -> Vec<(usize, usize)> {
+    let mut table = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            table[i][j] = if a[i] == b[j] {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if table[i + 1][j] >= table[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// `value` in flow style, e.g. `[ a, b ]`, which fits in any position.
+fn flow(value: &Value) -> Option<String> {
+    Some(match value {
+        Value::Sequence(items) if items.is_empty() => String::from("[]"),
+        Value::Sequence(items) => format!(
+            "[ {} ]",
+            items
+                .iter()
+                .map(flow)
+                .collect::<Option<Vec<_>>>()?
+                .join(", ")
+        ),
+        Value::Mapping(entries) if entries.is_empty() => String::from("{}"),
+        Value::Mapping(entries) => format!(
+            "{{ {} }}",
+            entries
+                .iter()
+                .map(|(key, value)| Some(format!("{}: {}", flow(key)?, flow(value)?)))
+                .collect::<Option<Vec<_>>>()?
+                .join(", ")
+        ),
+        Value::String(s) => {
+            let yaml = serde_yaml::to_string(s).ok()?;
+            let yaml = yaml.trim_end();
+            if yaml.contains(['\n', ',', '[', ']', '{', '}']) || yaml.starts_with(['|', '>']) {
+                serde_json::to_string(s).ok()?
+            } else {
+                yaml.to_string()
+            }
+        }
+        Value::Tagged(_) => return None,
+        scalar => serde_yaml::to_string(scalar).ok()?.trim_end().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::patch::patch;
+
+    fn check(source: &str, new: &str, expected: &str) {
+        let old: Value = serde_yaml::from_str(source).unwrap();
+        let new: Value = serde_yaml::from_str(new).unwrap();
+        let patched = patch(source, &old, &new, &|_, _| {}).unwrap();
+        assert_eq!(patched, expected);
+        assert_eq!(serde_yaml::from_str::<Value>(&patched).unwrap(), new);
+    }
+
+    #[test]
+    fn must_keep_comments() {
+        let source = "\
+# Header.
+a: 1 # Trailing.
+# Before b.
+b:
+  # Inside b.
+  c: [ x, y ]
+  d: \"quoted\"
+e:
+  - one
+  # Between items.
+  - two
+";
+        // Scalars and flow collections are replaced in place.
+        check(
+            source,
+            "{ a: 2, b: { c: [ x ], d: 'it''s' }, e: [ one, two ] }",
+            &source
+                .replace("a: 1", "a: 2")
+                .replace("[ x, y ]", "[ x ]")
+                .replace("\"quoted\"", "it's"),
+        );
+        // Entries are inserted after preceding ones, and removed with their lines.
+        check(
+            source,
+            "{ z: 0, a: 1, b: { d: quoted, f: [ 1 ] }, e: [ one, two ], g: true }",
+            "\
+# Header.
+z: 0
+a: 1 # Trailing.
+# Before b.
+b:
+  # Inside b.
+  d: \"quoted\"
+  f:
+  - 1
+e:
+  - one
+  # Between items.
+  - two
+g: true
+",
+        );
+        // Sequence items are inserted and removed around unchanged ones.
+        check(
+            source,
+            "{ a: 1, b: { c: [ x, y ], d: quoted }, e: [ zero, two, three ] }",
+            "\
+# Header.
+a: 1 # Trailing.
+# Before b.
+b:
+  # Inside b.
+  c: [ x, y ]
+  d: \"quoted\"
+e:
+  - zero
+  # Between items.
+  - two
+  - three
+",
+        );
+    }
+
+    #[test]
+    fn must_patch_nested() {
+        let source = "\
+list:
+  - name: a # First.
+    tags: [ x ]
+  - name: b
+empty:
+";
+        check(
+            source,
+            "{ list: [ { name: a, tags: [ x, y ] }, { name: b, extra: 1 } ], empty: 3 }",
+            "\
+list:
+  - name: a # First.
+    tags: [ x, y ]
+  - name: b
+    extra: 1
+empty: 3
+",
+        );
+        // Collections emptied are written in flow style.
+        check(source, "{ list: [], empty: null }", "list:\n  []\nempty:\n");
+    }
+
+    #[test]
+    fn must_refuse_non_block_root() {
+        let value: Value = serde_yaml::from_str("{ a: 1 }").unwrap();
+        assert!(patch("{ a: 1 }", &value, &value, &|_, _| {}).is_none());
+    }
+}
@@ -0,0 +1,294 @@
+//! File name patterns used by rules.
+//!
+//! A pattern is either a literal file name, which is looked up directly, a glob like
+//! `*.egg-info`, or a regex. Globs and regexes are compiled once when the config is loaded.
+//!
+//! A literal pattern may also be a nested relative path like `android/app/build`, which is
+//! resolved against the directory the rule is evaluated in.
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::{Component, Path, PathBuf};
+use std::{fs, io};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{Regex, RegexSet};
+
+/// Characters that turn a pattern into a glob.
+const GLOB_CHARS: [char; 4] = ['*', '?', '[', '{'];
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(GLOB_CHARS)
+}
+
+/// A pattern that failed to compile.
+#[derive(Debug)]
+pub struct PatternError {
+    /// The offending pattern.
+    pub pattern: String,
+    /// The underlying parse error.
+    pub source: Box<dyn Error + Send + Sync>,
+}
+
+impl PatternError {
+    fn new(pattern: impl Into<String>, source: impl Error + Send + Sync + 'static) -> Self {
+        Self {
+            pattern: pattern.into(),
+            source: Box::new(source),
+        }
+    }
+}
+
+/// A compiled set of file name patterns.
+#[derive(Debug, Clone)]
+pub struct Patterns {
+    literals: HashSet<PathBuf>,
+    nested: Vec<PathBuf>,
+    globs: GlobSet,
+    regexes: RegexSet,
+}
+
+impl Default for Patterns {
+    fn default() -> Self {
+        Self {
+            literals: HashSet::new(),
+            nested: Vec::new(),
+            globs: GlobSet::empty(),
+            regexes: RegexSet::empty(),
+        }
+    }
+}
+
+impl Patterns {
+    /// Compile given literal names, nested paths or globs, and regexes.
+    ///
+    /// Nested paths are always literal. Regexes are searched anywhere in the file name, so anchor them with `^` and `$` if needed.
+    ///
+    /// # Errors
+    /// Returns the offending pattern and the error if some glob or regex is invalid, or some nested
+    /// path is not a normalized relative path.
+    pub fn new<'a>(
+        patterns: impl IntoIterator<Item = &'a PathBuf>,
+        regexes: &[String],
+    ) -> Result<Self, PatternError> {
+        let mut literals = HashSet::new();
+        let mut nested = Vec::new();
+        let mut globs = GlobSetBuilder::new();
+        let mut last_glob = None;
+        for pattern in patterns {
+            if pattern.components().nth(1).is_some() {
+                if !pattern
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+                {
+                    return Err(PatternError::new(
+                        pattern.to_string_lossy(),
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "nested path must be relative and must not contain `.` or `..`",
+                        ),
+                    ));
+                }
+                nested.push(pattern.clone());
+                continue;
+            }
+            match pattern.to_str() {
+                Some(s) if is_glob(s) => {
+                    let glob = GlobBuilder::new(s)
+                        .literal_separator(true)
+                        .build()
+                        .map_err(|e| PatternError::new(s, e))?;
+                    globs.add(glob);
+                    last_glob = Some(s);
+                }
+                _ => {
+                    literals.insert(pattern.clone());
+                }
+            }
+        }
+        let globs = match last_glob {
+            Some(pattern) => globs.build().map_err(|e| PatternError::new(pattern, e))?,
+            None => GlobSet::empty(),
+        };
+        // Compile regexes one by one first to find out the offending one.
+        for regex in regexes {
+            Regex::new(regex).map_err(|e| PatternError::new(regex.as_str(), e))?;
+        }
+        let regexes = RegexSet::new(regexes).map_err(|e| PatternError::new("", e))?;
+        Ok(Self {
+            literals,
+            nested,
+            globs,
+            regexes,
+        })
+    }
+    /// Whether there's no pattern at all.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.literals.is_empty()
+            && self.nested.is_empty()
+            && self.globs.is_empty()
+            && self.regexes.is_empty()
+    }
+    fn is_pattern_match(&self, name: &Path) -> bool {
+        (!self.globs.is_empty() && self.globs.is_match(name))
+            || (!self.regexes.is_empty() && self.regexes.is_match(&name.to_string_lossy()))
+    }
+    /// Check if a file name matches any of the patterns, nested paths excluded.
+    #[must_use]
+    pub fn is_match(&self, name: &Path) -> bool {
+        // Literal names are the common case, so try them first.
+        self.literals.contains(name) || self.is_pattern_match(name)
+    }
+    /// Directories against which some nested path resolves to `dir/name`.
+    ///
+    /// E.g. given nested path `android/app/build`, `/proj` is yielded for
+    /// `dir = /proj/android/app` and `name = build`.
+    pub fn nested_bases<'a>(
+        &'a self,
+        dir: &'a Path,
+        name: &'a Path,
+    ) -> impl Iterator<Item = &'a Path> + 'a {
+        self.nested.iter().filter_map(move |pattern| {
+            let parent = pattern.parent()?;
+            if pattern.file_name()? == name.as_os_str() && dir.ends_with(parent) {
+                dir.ancestors().nth(parent.components().count())
+            } else {
+                None
+            }
+        })
+    }
+    /// Check if any of the names in a listing of `dir` matches any of the patterns.
+    ///
+    /// Nested paths are looked up on disk.
+    #[must_use]
+    pub fn matches_any<V>(&self, dir: &Path, names: &HashMap<PathBuf, V>) -> bool {
+        self.literals.iter().any(|name| names.contains_key(name))
+            || names.keys().any(|name| self.is_pattern_match(name))
+            || self.nested_exists_in(dir)
+    }
+    /// Check if any of the entries in `dir` matches any of the patterns, reading `dir` from disk.
+    #[must_use]
+    pub fn matches_any_in(&self, dir: &Path) -> bool {
+        let exists = |path: &PathBuf| dir.join(path).symlink_metadata().is_ok();
+        self.literals.iter().any(exists)
+            || self.nested_exists_in(dir)
+            || ((!self.globs.is_empty() || !self.regexes.is_empty())
+                && fs::read_dir(dir)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .any(|entry| self.is_pattern_match(Path::new(&entry.file_name()))))
+    }
+    /// Entries in `dir` matching any of the patterns, reading `dir` from disk.
+    #[must_use]
+    pub fn matches_in(&self, dir: &Path) -> Vec<PathBuf> {
+        let exists = |path: &&PathBuf| dir.join(path).symlink_metadata().is_ok();
+        let mut matches: Vec<_> = self
+            .literals
+            .iter()
+            .chain(&self.nested)
+            .filter(exists)
+            .cloned()
+            .collect();
+        if !self.globs.is_empty() || !self.regexes.is_empty() {
+            matches.extend(
+                fs::read_dir(dir)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(|entry| PathBuf::from(entry.file_name()))
+                    .filter(|name| !self.literals.contains(name) && self.is_pattern_match(name)),
+            );
+        }
+        matches
+    }
+    fn nested_exists_in(&self, dir: &Path) -> bool {
+        self.nested
+            .iter()
+            .any(|path| dir.join(path).symlink_metadata().is_ok())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use crate::pattern::Patterns;
+
+    fn patterns(patterns: &[&str]) -> Patterns {
+        let patterns: Vec<_> = patterns.iter().map(PathBuf::from).collect();
+        Patterns::new(&patterns, &[]).unwrap()
+    }
+
+    #[test]
+    fn must_match_literal_and_glob() {
+        let patterns = patterns(&["target", "*.egg-info", "cmake-build-*", ".terraform*"]);
+        for name in [
+            "target",
+            "foo.egg-info",
+            "cmake-build-debug",
+            ".terraform",
+            ".terraform.lock.hcl",
+        ] {
+            assert!(patterns.is_match(Path::new(name)), "{}", name);
+        }
+        for name in ["targets", "egg-info", "cmake-build", "terraform"] {
+            assert!(!patterns.is_match(Path::new(name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn must_match_any_in_listing() {
+        let listing: HashMap<_, _> = [(PathBuf::from("App.xcodeproj"), ())].into();
+        let dir = Path::new("/nonexistent");
+        assert!(patterns(&["*.xcodeproj"]).matches_any(dir, &listing));
+        assert!(!patterns(&["*.xcworkspace", "Podfile"]).matches_any(dir, &listing));
+        assert!(!patterns(&[]).matches_any(dir, &listing));
+    }
+
+    #[test]
+    fn must_match_regex() {
+        let patterns = Patterns::new(&[], &[r"^build-(debug|release)-\d+$".to_string()]).unwrap();
+        assert!(patterns.is_match(Path::new("build-debug-42")));
+        assert!(!patterns.is_match(Path::new("build-debug")));
+        assert!(!patterns.is_match(Path::new("build-profile-1")));
+
+        let listing: HashMap<_, _> = [(PathBuf::from("build-release-1"), ())].into();
+        assert!(patterns.matches_any(Path::new("/nonexistent"), &listing));
+    }
+
+    #[test]
+    fn must_resolve_nested_bases() {
+        let patterns = patterns(&["android/app/build", "ios/Pods", "build"]);
+        assert!(!patterns.is_match(Path::new("Pods")));
+
+        let bases = |dir: &str, name: &str| {
+            patterns
+                .nested_bases(Path::new(dir), Path::new(name))
+                .map(Path::to_path_buf)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            bases("/proj/android/app", "build"),
+            [PathBuf::from("/proj")]
+        );
+        assert_eq!(bases("/proj/ios", "Pods"), [PathBuf::from("/proj")]);
+        assert!(bases("/proj/ios", "build").is_empty());
+        assert!(bases("/proj/app", "build").is_empty());
+    }
+
+    #[test]
+    fn must_reject_invalid_pattern() {
+        let invalid = [PathBuf::from("[a-")];
+        let e = Patterns::new(&invalid, &[]).unwrap_err();
+        assert_eq!(e.pattern, "[a-");
+
+        let e = Patterns::new(&[], &["ok".to_string(), "(unclosed".to_string()]).unwrap_err();
+        assert_eq!(e.pattern, "(unclosed");
+
+        let invalid = [PathBuf::from("../build")];
+        let e = Patterns::new(&invalid, &[]).unwrap_err();
+        assert_eq!(e.pattern, "../build");
+    }
+}
@@ -8,9 +8,10 @@ use std::sync::Arc;
 
 use parking_lot::Mutex;
 use serde_json::{Map, Value};
-use tauri::{AppHandle, Manager, Runtime};
 use tracing::error;
 
+use crate::event::{Event, EventSink};
+
 #[derive(Debug, Clone)]
 pub struct Store {
     data: Arc<Mutex<Map<String, Value>>>,
@@ -47,16 +48,16 @@ impl Store {
         let data = self.data.lock();
         data.get(key).cloned()
     }
-    pub fn set<R: Runtime>(&self, handle: &AppHandle<R>, key: String, value: Value) {
+    pub fn set(&self, events: &dyn EventSink, key: String, value: Value) {
         let mut data = self.data.lock();
         data.insert(key, value);
         std::fs::write(&self.path, serde_json::to_vec(&*data).unwrap()).unwrap();
-        drop(handle.emit_all("properties_changed", data.clone()));
+        events.emit(Event::PropertiesChanged(data.clone()));
     }
-    pub fn del<R: Runtime>(&self, handle: &AppHandle<R>, key: &str) {
+    pub fn del(&self, events: &dyn EventSink, key: &str) {
         let mut data = self.data.lock();
         data.remove(key);
         std::fs::write(&self.path, serde_json::to_vec(&*data).unwrap()).unwrap();
-        drop(handle.emit_all("properties_changed", data.clone()));
+        events.emit(Event::PropertiesChanged(data.clone()));
     }
 }
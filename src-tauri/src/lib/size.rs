@@ -0,0 +1,138 @@
+//! Disk usage of directories.
+//!
+//! Sizes are cached for a while, so that the watcher doesn't walk large trees on every event. The
+//! watcher invalidates them once something changes within.
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use jwalk::rayon::{ThreadPool, ThreadPoolBuilder};
+use jwalk::{Parallelism, WalkDir};
+use moka::sync::Cache;
+use once_cell::sync::Lazy;
+
+const CACHE_MAX_CAPACITY: u64 = 4096;
+const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Sizes are usually computed from within a walk, which keeps the default rayon pool busy, so they
+/// get a pool of their own.
+static POOL: Lazy<Arc<ThreadPool>> = Lazy::new(|| {
+    Arc::new(
+        ThreadPoolBuilder::new()
+            .thread_name(|i| format!("size-{i}"))
+            .build()
+            .expect("failed to build thread pool"),
+    )
+});
+
+static CACHE: Lazy<Cache<PathBuf, Known>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(CACHE_MAX_CAPACITY)
+        .time_to_live(CACHE_TTL)
+        .build()
+});
+
+/// What is known about the size of a path.
+#[derive(Debug, Copy, Clone)]
+struct Known {
+    /// Lower bound of the size, in bytes.
+    size: u64,
+    /// Whether `size` is exact, i.e. the walk wasn't stopped early.
+    complete: bool,
+}
+
+/// Check if the total size of files in a directory is at least `min` bytes.
+///
+/// Files are visited in parallel, and the walk stops as soon as the threshold is reached. Results
+/// are cached, so a path may be walked again only if a larger threshold is asked.
+#[must_use]
+pub fn is_at_least(path: &Path, min: u64) -> bool {
+    if min == 0 {
+        return true;
+    }
+    if let Some(known) = CACHE.get(path) {
+        if known.size >= min {
+            return true;
+        }
+        if known.complete {
+            return false;
+        }
+    }
+    let mut size = 0;
+    let reached = WalkDir::new(path)
+        .skip_hidden(false)
+        .parallelism(Parallelism::RayonExistingPool {
+            pool: POOL.clone(),
+            busy_timeout: None,
+        })
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.file_type().is_dir())
+        .filter_map(|entry| entry.metadata().ok())
+        .any(|metadata| {
+            size += metadata.len();
+            size >= min
+        });
+    CACHE.insert(
+        path.to_path_buf(),
+        Known {
+            size,
+            complete: !reached,
+        },
+    );
+    reached
+}
+
+/// Forget cached sizes of a changed path and its ancestors, which all include it.
+pub fn invalidate(path: &Path) {
+    for ancestor in path.ancestors() {
+        CACHE.invalidate(ancestor);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::size::{invalidate, is_at_least};
+
+    #[test]
+    fn must_check_size() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join("a/b")).unwrap();
+        fs::write(temp_dir.path().join("a/b/c"), [0; 64]).unwrap();
+        fs::write(temp_dir.path().join("a/d"), [0; 64]).unwrap();
+
+        assert!(is_at_least(temp_dir.path(), 0));
+        assert!(is_at_least(temp_dir.path(), 128));
+        assert!(!is_at_least(temp_dir.path(), 129));
+    }
+
+    #[test]
+    fn must_cache_size() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a"), [0; 64]).unwrap();
+        assert!(!is_at_least(temp_dir.path(), 128));
+
+        // The exact size is cached, so the new file isn't seen yet.
+        fs::write(temp_dir.path().join("b"), [0; 64]).unwrap();
+        assert!(!is_at_least(temp_dir.path(), 128));
+        assert!(is_at_least(temp_dir.path(), 64));
+    }
+
+    #[test]
+    fn must_invalidate_ancestors() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join("a/b")).unwrap();
+        fs::write(temp_dir.path().join("a/b/c"), [0; 64]).unwrap();
+        assert!(!is_at_least(temp_dir.path(), 128));
+        assert!(!is_at_least(&temp_dir.path().join("a"), 128));
+
+        fs::write(temp_dir.path().join("a/b/d"), [0; 64]).unwrap();
+        invalidate(&temp_dir.path().join("a/b"));
+        assert!(is_at_least(temp_dir.path(), 128));
+        assert!(is_at_least(&temp_dir.path().join("a"), 128));
+    }
+}
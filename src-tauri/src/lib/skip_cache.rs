@@ -1,11 +1,15 @@
 //! Cache facilities used in walker.
 
 use std::borrow::Borrow;
+use std::fs::File;
+use std::io::Read;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use moka::sync::Cache;
+use regex::Regex;
+use tracing::debug;
 
 const CACHE_MAX_CAPACITY: u64 = 512;
 
@@ -27,6 +31,40 @@ impl Deref for SkipCache {
     }
 }
 
+/// Only this many bytes of a file are read when checking its content.
+const CONTENT_MAX_LEN: u64 = 1024 * 1024;
+
+/// Cache for file content checks, so that a file is read at most once per pattern.
+#[derive(Clone)]
+pub struct ContentCache(Arc<Cache<(PathBuf, String), bool>>);
+
+impl Default for ContentCache {
+    fn default() -> Self {
+        Self(Arc::new(Cache::new(CACHE_MAX_CAPACITY)))
+    }
+}
+
+impl ContentCache {
+    /// Check if the content of given file matches the regex.
+    ///
+    /// Missing or unreadable files never match.
+    pub fn contains(&self, path: &Path, regex: &Regex) -> bool {
+        self.0
+            .get_with((path.to_path_buf(), regex.as_str().to_string()), || {
+                let mut content = Vec::new();
+                match File::open(path)
+                    .and_then(|f| f.take(CONTENT_MAX_LEN).read_to_end(&mut content))
+                {
+                    Ok(_) => regex.is_match(&String::from_utf8_lossy(&content)),
+                    Err(e) => {
+                        debug!("Failed to read {:?}: {}", path, e);
+                        false
+                    }
+                }
+            })
+    }
+}
+
 /// Custom `Path` wrapper to implement `Borrow` for Arc<PathBuf>.
 #[repr(transparent)]
 #[derive(Debug, Eq, PartialEq, Hash)]
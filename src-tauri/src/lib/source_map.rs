@@ -0,0 +1,440 @@
+//! Locations of nodes in a YAML document.
+//!
+//! `serde_yaml` only reports locations of syntax and type errors, so problems found after
+//! deserialization are located by looking up the path to the offending node here. Spans of nodes
+//! are recorded too, so that documents can be patched in place.
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use serde::Serialize;
+use ts_rs::TS;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+
+/// A position in a YAML document.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct Location {
+    /// Line number, starting from 1.
+    pub line: usize,
+    /// Column number, starting from 1.
+    pub column: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl From<Marker> for Location {
+    fn from(marker: Marker) -> Self {
+        Self {
+            line: marker.line(),
+            column: marker.col() + 1,
+        }
+    }
+}
+
+impl From<serde_yaml::Location> for Location {
+    fn from(location: serde_yaml::Location) -> Self {
+        Self {
+            line: location.line(),
+            column: location.column(),
+        }
+    }
+}
+
+/// A step on the path from the document root to a node.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Segment {
+    /// Value of a mapping entry.
+    Key(String),
+    /// Item of a sequence.
+    Index(usize),
+}
+
+impl From<&str> for Segment {
+    fn from(key: &str) -> Self {
+        Self::Key(key.to_string())
+    }
+}
+
+impl From<usize> for Segment {
+    fn from(index: usize) -> Self {
+        Self::Index(index)
+    }
+}
+
+/// Build a path to a node, e.g. `node_path!["directories", 0, "rules"]`.
+macro_rules! node_path {
+    ($($segment: expr),* $(,)?) => {
+        [$($crate::source_map::Segment::from($segment)),*]
+    };
+}
+pub(crate) use node_path;
+
+/// How a node is written in a YAML document.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Style {
+    /// A scalar or an alias.
+    Scalar,
+    /// A collection in flow style, e.g. `[ a, b ]`.
+    Flow,
+    /// A mapping in block style.
+    BlockMapping,
+    /// A sequence in block style.
+    BlockSequence,
+    /// A missing value, e.g. of `key:`. It has no span.
+    Empty,
+}
+
+/// A node in a YAML document.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Node {
+    /// Where the node starts.
+    pub location: Location,
+    /// Byte offset where the node starts.
+    pub start: usize,
+    /// Byte offset where the node ends, excluding trailing comments.
+    pub end: usize,
+    /// How the node is written.
+    pub style: Style,
+}
+
+/// Locations of nodes in a YAML document, by their path.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    nodes: HashMap<Vec<Segment>, Node>,
+    keys: HashMap<Vec<Segment>, Node>,
+}
+
+impl SourceMap {
+    /// Index nodes in a YAML document.
+    ///
+    /// Returns `None` if the document isn't valid YAML.
+    #[must_use]
+    pub fn new(source: &str) -> Option<Self> {
+        let mut builder = Builder {
+            chars: source.chars().collect(),
+            offsets: source
+                .char_indices()
+                .map(|(offset, _)| offset)
+                .chain([source.len()])
+                .collect(),
+            ..Builder::default()
+        };
+        Parser::new(source.chars()).load(&mut builder, false).ok()?;
+        Some(builder.map)
+    }
+    /// Location of the node at given path.
+    #[must_use]
+    pub fn node(&self, path: &[Segment]) -> Option<Location> {
+        self.nodes.get(path).map(|node| node.location)
+    }
+    /// Location of the key of the mapping entry at given path, or of the node itself if it's not a
+    /// mapping entry.
+    #[must_use]
+    pub fn key(&self, path: &[Segment]) -> Option<Location> {
+        self.keys
+            .get(path)
+            .map(|node| node.location)
+            .or_else(|| self.node(path))
+    }
+    /// The node at given path.
+    #[must_use]
+    pub fn get(&self, path: &[Segment]) -> Option<&Node> {
+        self.nodes.get(path)
+    }
+    /// The key of the mapping entry at given path.
+    #[must_use]
+    pub fn get_key(&self, path: &[Segment]) -> Option<&Node> {
+        self.keys.get(path)
+    }
+}
+
+enum Frame {
+    Mapping { key: Option<String>, end: usize },
+    Sequence { index: usize, end: usize },
+}
+
+impl Frame {
+    fn end(&mut self) -> &mut usize {
+        match self {
+            Self::Mapping { end, .. } | Self::Sequence { end, .. } => end,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    map: SourceMap,
+    path: Vec<Segment>,
+    frames: Vec<Frame>,
+    chars: Vec<char>,
+    /// Byte offsets of chars, followed by the length of the document.
+    offsets: Vec<usize>,
+    /// Node whose end is only known when the next event starts.
+    pending: Option<Vec<Segment>>,
+}
+
+impl Builder {
+    /// Path of the node starting now, or `None` if it's a mapping key.
+    fn enter(&mut self, event: &Event, node: Node) -> Option<Vec<Segment>> {
+        let segment = match self.frames.last_mut() {
+            None => None,
+            Some(Frame::Mapping {
+                key: key @ None, ..
+            }) => {
+                let name = match event {
+                    Event::Scalar(name, ..) => name.clone(),
+                    // Complex keys can't be referred to by path.
+                    _ => String::new(),
+                };
+                *key = Some(name.clone());
+                // Block mappings are marked after their first key, so they start at it instead.
+                if let Some(mapping) = self.map.nodes.get_mut(&self.path) {
+                    if mapping.style == Style::BlockMapping && node.start < mapping.start {
+                        mapping.location = node.location;
+                        mapping.start = node.start;
+                    }
+                }
+                let mut path = self.path.clone();
+                path.push(Segment::Key(name));
+                self.map.keys.insert(path, node);
+                return None;
+            }
+            Some(Frame::Mapping { key: Some(key), .. }) => Some(Segment::Key(key.clone())),
+            Some(Frame::Sequence { index, .. }) => Some(Segment::Index(*index)),
+        };
+        let mut path = self.path.clone();
+        path.extend(segment);
+        self.map.nodes.insert(path.clone(), node);
+        Some(path)
+    }
+    /// A node at the top of the stack ended.
+    fn leave(&mut self, end: usize) {
+        match self.frames.last_mut() {
+            Some(Frame::Mapping { key, .. }) => *key = None,
+            Some(Frame::Sequence { index, .. }) => *index += 1,
+            None => return,
+        }
+        if let Some(frame) = self.frames.last_mut() {
+            *frame.end() = (*frame.end()).max(end);
+        }
+    }
+    /// A node starting at `mark` and ending at char `end`.
+    fn node(&self, mark: Marker, style: Style, end: usize) -> Node {
+        Node {
+            location: mark.into(),
+            start: self.offsets[mark.index()],
+            end: self.offsets[end],
+            style,
+        }
+    }
+    /// End of a scalar starting at char `start`, or `None` if it's unknown yet.
+    fn scalar_end(&self, start: usize, value: &str, style: TScalarStyle) -> Option<usize> {
+        let chars = &self.chars[start..];
+        match style {
+            TScalarStyle::Plain => {
+                let len = value.chars().count();
+                (chars.len() >= len && chars[..len].iter().copied().eq(value.chars()))
+                    .then(|| start + len)
+            }
+            TScalarStyle::SingleQuoted => {
+                let mut i = 1;
+                while i < chars.len() {
+                    match (chars[i], chars.get(i + 1)) {
+                        ('\'', Some('\'')) => i += 2,
+                        ('\'', _) => return Some(start + i + 1),
+                        _ => i += 1,
+                    }
+                }
+                None
+            }
+            TScalarStyle::DoubleQuoted => {
+                let mut i = 1;
+                while i < chars.len() {
+                    match chars[i] {
+                        '\\' => i += 2,
+                        '"' => return Some(start + i + 1),
+                        _ => i += 1,
+                    }
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+    /// End the pending node right before `mark`, skipping whitespaces and comments in between.
+    fn resolve_pending(&mut self, mark: Marker) {
+        let Some(path) = self.pending.take() else {
+            return;
+        };
+        let Some(node) = self.map.nodes.get_mut(&path) else {
+            return;
+        };
+        let mut end = mark.index();
+        loop {
+            while end > 0 && self.chars[end - 1].is_whitespace() {
+                end -= 1;
+            }
+            let line_start = self.chars[..end]
+                .iter()
+                .rposition(|c| *c == '\n')
+                .map_or(0, |i| i + 1);
+            let is_comment = self.chars[line_start..end]
+                .iter()
+                .find(|c| !c.is_whitespace())
+                == Some(&'#');
+            if !is_comment || self.offsets[line_start] <= node.start {
+                break;
+            }
+            end = line_start;
+        }
+        node.end = self.offsets[end].max(node.start);
+        let end = node.end;
+        if let Some(frame) = self.frames.last_mut() {
+            *frame.end() = (*frame.end()).max(end);
+        }
+    }
+}
+
+impl MarkedEventReceiver for Builder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        self.resolve_pending(mark);
+        let start = mark.index();
+        match event {
+            Event::Scalar(ref value, style, ..) => {
+                let end = self.scalar_end(start, value, style);
+                let node = match end {
+                    Some(end) => self.node(mark, Style::Scalar, end),
+                    None if style == TScalarStyle::Plain && (value == "~" || value.is_empty()) => {
+                        self.node(mark, Style::Empty, start)
+                    }
+                    None => self.node(mark, Style::Scalar, start),
+                };
+                let path = self.enter(&event, node);
+                if end.is_none() && node.style == Style::Scalar {
+                    self.pending = path.clone();
+                }
+                if path.is_some() {
+                    self.leave(node.end);
+                }
+            }
+            Event::Alias(_) => {
+                let node = self.node(mark, Style::Scalar, start);
+                let path = self.enter(&event, node);
+                self.pending = path.clone();
+                if path.is_some() {
+                    self.leave(self.offsets[start]);
+                }
+            }
+            Event::MappingStart(_) | Event::SequenceStart(_) => {
+                let is_mapping = matches!(event, Event::MappingStart(_));
+                let style = match (self.chars.get(start), is_mapping) {
+                    (Some('{' | '['), _) => Style::Flow,
+                    (_, true) => Style::BlockMapping,
+                    (_, false) => Style::BlockSequence,
+                };
+                // Complex keys can't be referred to by path, so their nodes are indexed under an
+                // empty key.
+                let node = self.node(mark, style, start);
+                let path = self.enter(&event, node).unwrap_or_else(|| {
+                    let mut path = self.path.clone();
+                    path.push(Segment::Key(String::new()));
+                    path
+                });
+                self.path = path;
+                let end = self.offsets[start];
+                self.frames.push(if is_mapping {
+                    Frame::Mapping { key: None, end }
+                } else {
+                    Frame::Sequence { index: 0, end }
+                });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                let end = self.frames.pop().map_or(0, |mut frame| *frame.end());
+                let node = self.map.nodes.get_mut(&self.path);
+                let end = match node {
+                    Some(node) => {
+                        node.end = if node.style == Style::Flow {
+                            // Right after the closing bracket.
+                            self.offsets[(start + 1).min(self.chars.len())]
+                        } else {
+                            end
+                        };
+                        node.end
+                    }
+                    None => end,
+                };
+                self.path.pop();
+                self.leave(end);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::source_map::{Location, SourceMap, Style};
+
+    #[test]
+    fn must_locate_nodes() {
+        let source = "\
+directories:
+  - path: ~/
+    rules: [ a, \"b\" ]
+rules:
+  a:
+    excludes: [ target ]
+  b: [ a ]
+";
+        let map = SourceMap::new(source).unwrap();
+        let location = |line, column| Some(Location { line, column });
+        assert_eq!(map.node(&node_path!["directories"]), location(2, 3));
+        assert_eq!(
+            map.node(&node_path!["directories", 0, "path"]),
+            location(2, 11)
+        );
+        assert_eq!(
+            map.node(&node_path!["directories", 0, "rules", 1]),
+            location(3, 17)
+        );
+        assert_eq!(map.key(&node_path!["rules", "b"]), location(7, 3));
+        assert_eq!(map.node(&node_path!["rules", "b", 0]), location(7, 8));
+        assert_eq!(map.node(&node_path!["rules", "c"]), None);
+
+        assert!(SourceMap::new("a: [").is_none());
+    }
+
+    #[test]
+    fn must_span_nodes() {
+        let source = "\
+a: 'it''s' # Comment.
+b:
+  c: [ \"x\" ]
+  d: |
+    text
+
+  # Comment.
+e:
+f: ü
+";
+        let map = SourceMap::new(source).unwrap();
+        let span = |path: &[_]| {
+            let node = map.get(path).unwrap();
+            (&source[node.start..node.end], node.style)
+        };
+        assert_eq!(span(&node_path!["a"]), ("'it''s'", Style::Scalar));
+        assert_eq!(
+            span(&node_path!["b"]),
+            ("c: [ \"x\" ]\n  d: |\n    text", Style::BlockMapping)
+        );
+        assert_eq!(span(&node_path!["b", "c"]), ("[ \"x\" ]", Style::Flow));
+        assert_eq!(span(&node_path!["e"]).1, Style::Empty);
+        assert_eq!(span(&node_path!["f"]), ("ü", Style::Scalar));
+        assert_eq!(span(&[]).0, source.trim_end());
+    }
+}
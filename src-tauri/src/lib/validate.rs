@@ -0,0 +1,514 @@
+//! Config validation.
+//!
+//! Unlike loading a config, which stops at the first error, validation reports every problem at
+//! once, located in the config file if its source is known.
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::config::{absolute, expand_all, CompiledRule, PreConfig, PreRule, Protect};
+use crate::error::ConfigIOError;
+use crate::migration::{self, VERSION_KEY};
+use crate::source_map::{node_path, Location, Segment, SourceMap};
+
+/// How serious a problem is.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    /// The config can't be loaded.
+    Error,
+    /// The config can be loaded, but probably doesn't do what's intended.
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        })
+    }
+}
+
+/// A problem found in a config.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct Diagnostic {
+    /// How serious the problem is.
+    pub severity: Severity,
+    /// Description of the problem.
+    pub message: String,
+    /// Where the problem is in the config file, if known.
+    pub location: Option<Location>,
+}
+
+impl Diagnostic {
+    /// An error without location.
+    pub fn error(e: &dyn Error) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: describe(e),
+            location: None,
+        }
+    }
+    /// Whether the problem prevents the config from being loaded.
+    #[must_use]
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+    fn from_yaml(e: &serde_yaml::Error) -> Self {
+        Self {
+            location: e.location().map(Location::from),
+            ..Self::error(e)
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(location) = self.location {
+            write!(f, "{location}: ")?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Describe an error along with its sources.
+fn describe(e: &dyn Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
+    }
+    message
+}
+
+/// Validate the source of the config file at `path`.
+///
+/// Configs written by older versions are migrated first. Problems can't be located if migration
+/// changed more than the version, since the migrated config differs from the source. Included
+/// rule packs are resolved relative to `path`.
+#[must_use]
+pub fn validate_source(content: &str, path: &Path) -> Vec<Diagnostic> {
+    let source = SourceMap::new(content);
+    let mut value: serde_yaml::Value = match serde_yaml::from_str(content) {
+        Ok(value) => value,
+        Err(e) => return vec![Diagnostic::from_yaml(&e)],
+    };
+    let original = value.clone();
+    match migration::migrate(&mut value) {
+        Ok(_) => (),
+        Err(e) => {
+            return vec![Diagnostic {
+                location: source
+                    .as_ref()
+                    .and_then(|source| source.node(&node_path![VERSION_KEY])),
+                ..Diagnostic::error(&e)
+            }]
+        }
+    }
+    let located = migration::only_version_changed(original, value.clone());
+    let config = if located {
+        serde_yaml::from_str(content)
+    } else {
+        serde_yaml::from_value(value)
+    };
+    let mut config: PreConfig = match config {
+        Ok(config) => config,
+        Err(e) => return vec![Diagnostic::from_yaml(&e)],
+    };
+    let source = source.as_ref().filter(|_| located);
+    if let Err(e) = config.resolve_includes(path) {
+        // Locate the entry through which the failing pack is included.
+        let index = match &e {
+            ConfigIOError::Include { path, .. } => {
+                config.include.iter().position(|entry| entry == path)
+            }
+            _ => None,
+        };
+        let location = source.and_then(|source| match index {
+            Some(i) => source.node(&node_path!["include", i]),
+            None => source.node(&node_path!["include"]),
+        });
+        return vec![Diagnostic {
+            location,
+            ..Diagnostic::error(&e)
+        }];
+    }
+    validate(&config, source)
+}
+
+/// Validate a config, locating problems in `source` if given.
+///
+/// Included rule packs must be resolved beforehand, see [`PreConfig::resolve_includes`].
+#[must_use]
+pub fn validate(config: &PreConfig, source: Option<&SourceMap>) -> Vec<Diagnostic> {
+    let rules = config.all_rules();
+    let mut validator = Validator {
+        config,
+        rules: &rules,
+        source,
+        diagnostics: vec![],
+    };
+    validator.backends();
+    validator.rules();
+    let roots = validator.directories();
+    validator.unused_rules();
+    validator.skips(&roots);
+    validator.protect();
+    let mut diagnostics = validator.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| {
+        diagnostic.location.map_or((true, 0, 0), |location| {
+            (false, location.line, location.column)
+        })
+    });
+    diagnostics
+}
+
+struct Validator<'a> {
+    config: &'a PreConfig,
+    /// Local and included rules.
+    rules: &'a HashMap<String, PreRule>,
+    source: Option<&'a SourceMap>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator<'_> {
+    fn push(&mut self, severity: Severity, location: Option<Location>, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            message,
+            location,
+        });
+    }
+    /// Report a problem on the node at `path`.
+    fn node(&mut self, severity: Severity, path: &[Segment], message: String) {
+        let location = self.source.and_then(|source| source.node(path));
+        self.push(severity, location, message);
+    }
+    /// Report a problem on the key of the mapping entry at `path`.
+    fn key(&mut self, severity: Severity, path: &[Segment], message: String) {
+        let location = self.source.and_then(|source| source.key(path));
+        self.push(severity, location, message);
+    }
+    fn backends(&mut self) {
+        let kinds = self.config.backend_kinds();
+        if kinds.is_empty() && self.config.exclude_files.is_empty() {
+            self.node(
+                Severity::Error,
+                &node_path!["backends"],
+                String::from("No backend to apply exclusions with"),
+            );
+        }
+        for kind in kinds {
+            if let Err(e) = kind.build() {
+                // Backends not listed are implied by `support-dump`.
+                let path = match self
+                    .config
+                    .backends
+                    .iter()
+                    .flatten()
+                    .position(|k| *k == kind)
+                {
+                    Some(i) => node_path!["backends", i].to_vec(),
+                    None => node_path!["support-dump"].to_vec(),
+                };
+                self.node(Severity::Error, &path, describe(&e));
+            }
+        }
+    }
+    fn rules(&mut self) {
+        let rules = self.rules;
+        for name in rules.keys().sorted_unstable() {
+            match &rules[name] {
+                PreRule::Concrete(rule) => {
+                    if let Err(e) = CompiledRule::new(name, rule.clone()) {
+                        self.key(
+                            Severity::Error,
+                            &node_path!["rules", name.as_str()],
+                            describe(&e),
+                        );
+                    }
+                }
+                PreRule::Union(children) => {
+                    for (i, child) in children.iter().enumerate() {
+                        if !rules.contains_key(child) {
+                            self.node(
+                                Severity::Error,
+                                &node_path!["rules", name.as_str(), i],
+                                format!("Missing rule: {child}"),
+                            );
+                        }
+                    }
+                    if reachable(rules, children.iter().map(String::as_str)).contains(name.as_str())
+                    {
+                        self.key(
+                            Severity::Error,
+                            &node_path!["rules", name.as_str()],
+                            format!("Loop found in rules. Rule {name} references itself"),
+                        );
+                    }
+                }
+            }
+        }
+    }
+    /// Validate directories, returning canonical paths of valid ones.
+    fn directories(&mut self) -> Vec<PathBuf> {
+        let config = self.config;
+        if config.directories.is_empty() {
+            self.node(
+                Severity::Warning,
+                &node_path!["directories"],
+                String::from("No directory to scan"),
+            );
+        }
+        let mut roots = vec![];
+        let mut seen = HashSet::new();
+        for (i, directory) in config.directories.iter().enumerate() {
+            let path_at = node_path!["directories", i, "path"];
+            match PathBuf::from(shellexpand::tilde(&directory.path).as_ref()).canonicalize() {
+                Err(e) => self.node(
+                    Severity::Error,
+                    &path_at,
+                    format!("Specified path is invalid: {}: {e}", directory.path),
+                ),
+                Ok(path) if !path.is_dir() => self.node(
+                    Severity::Error,
+                    &path_at,
+                    format!("Specified path is not a directory: {}", directory.path),
+                ),
+                Ok(path) if !seen.insert(path.clone()) => self.node(
+                    Severity::Warning,
+                    &path_at,
+                    format!("Directory is listed more than once: {}", directory.path),
+                ),
+                Ok(path) => roots.push(path),
+            }
+            for (j, rule) in directory.rules.iter().enumerate() {
+                if !self.rules.contains_key(rule) {
+                    self.node(
+                        Severity::Error,
+                        &node_path!["directories", i, "rules", j],
+                        format!("Missing rule: {rule}"),
+                    );
+                }
+            }
+            for (j, tag) in directory.tags.iter().enumerate() {
+                if tagged(self.rules, tag).next().is_none() {
+                    self.node(
+                        Severity::Error,
+                        &node_path!["directories", i, "tags", j],
+                        format!("No rule is tagged with {tag}"),
+                    );
+                }
+            }
+            let protect = Protect::new(
+                &format!("{}#protect", directory.path),
+                expand_all(directory.protect.clone()),
+            );
+            if let Err(e) = protect {
+                self.node(
+                    Severity::Error,
+                    &node_path!["directories", i, "protect"],
+                    describe(&e),
+                );
+            }
+        }
+        roots
+    }
+    /// Included rules are not reported, since packs are shared by many configs.
+    fn unused_rules(&mut self) {
+        let rules = self.rules;
+        let used = reachable(
+            rules,
+            self.config.directories.iter().flat_map(|directory| {
+                directory
+                    .rules
+                    .iter()
+                    .map(String::as_str)
+                    .chain(directory.tags.iter().flat_map(|tag| tagged(rules, tag)))
+            }),
+        );
+        for name in self.config.rules.keys().sorted_unstable() {
+            if !used.contains(name.as_str()) {
+                self.key(
+                    Severity::Warning,
+                    &node_path!["rules", name.as_str()],
+                    format!("Rule {name} is not used by any directory"),
+                );
+            }
+        }
+    }
+    fn skips(&mut self, roots: &[PathBuf]) {
+        // Skips can't be checked against directories that are invalid anyway.
+        if roots.is_empty() {
+            return;
+        }
+        for (i, skip) in self.config.skips.iter().enumerate() {
+            let path = absolute(shellexpand::tilde(skip).as_ref());
+            let path = path.canonicalize().unwrap_or(path);
+            if !roots.iter().any(|root| path.starts_with(root)) {
+                self.node(
+                    Severity::Warning,
+                    &node_path!["skips", i],
+                    format!("Skipped path is outside of any directory: {skip}"),
+                );
+            }
+        }
+    }
+    fn protect(&mut self) {
+        if let Err(e) = Protect::new("protect", expand_all(self.config.protect.clone())) {
+            self.node(Severity::Error, &node_path!["protect"], describe(&e));
+        }
+    }
+}
+
+/// Names of concrete rules tagged with `tag`.
+fn tagged<'a>(rules: &'a HashMap<String, PreRule>, tag: &'a str) -> impl Iterator<Item = &'a str> {
+    rules
+        .iter()
+        .filter(move |(_, rule)| {
+            matches!(rule, PreRule::Concrete(rule) if rule.tags.iter().any(|t| t == tag))
+        })
+        .map(|(name, _)| name.as_str())
+}
+
+/// Names of rules reachable from `roots` through union rules, including the roots themselves.
+///
+/// Missing rules are ignored.
+fn reachable<'a>(
+    rules: &'a HashMap<String, PreRule>,
+    roots: impl IntoIterator<Item = &'a str>,
+) -> HashSet<&'a str> {
+    let mut visited = HashSet::new();
+    let mut stack: Vec<_> = roots.into_iter().collect();
+    while let Some(name) = stack.pop() {
+        let Some((name, rule)) = rules.get_key_value(name) else {
+            continue;
+        };
+        if visited.insert(name.as_str()) {
+            if let PreRule::Union(children) = rule {
+                stack.extend(children.iter().map(String::as_str));
+            }
+        }
+    }
+    visited
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use tempfile::TempDir;
+
+    use crate::source_map::Location;
+    use crate::validate::{validate_source, Severity};
+
+    #[test]
+    fn must_report_all_problems() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("dir")).unwrap();
+        let source = format!(
+            "\
+backends: [ cachedir-tag ]
+directories:
+  - path: {root}/dir
+    rules: [ a, missing ]
+  - path: {root}/dir
+    rules: [ loop ]
+  - path: {root}/nonexistent
+    rules: []
+    tags: [ unknown ]
+skips:
+  - {root}/dir/skipped
+  - /elsewhere
+rules:
+  a:
+    excludes: [ \"[\" ]
+  unused:
+    excludes: [ target ]
+  loop: [ a, loop ]
+",
+            root = root.display()
+        );
+        let diagnostics: Vec<_> = validate_source(&source, &root.join("tmexclude.yaml"))
+            .into_iter()
+            .map(|diagnostic| {
+                let Location { line, column } = diagnostic.location.unwrap();
+                (diagnostic.severity, line, column)
+            })
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                (Severity::Error, 4, 17),
+                (Severity::Warning, 5, 11),
+                (Severity::Error, 7, 11),
+                (Severity::Error, 9, 13),
+                (Severity::Warning, 12, 5),
+                (Severity::Error, 14, 3),
+                (Severity::Warning, 16, 3),
+                (Severity::Error, 18, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn must_locate_deserialize_errors() {
+        let path = Path::new("tmexclude.yaml");
+        let diagnostics = validate_source("directories:\n  - path: 1\n    rules: 2\n", path);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].is_error());
+        assert!(diagnostics[0].location.is_some());
+
+        let diagnostics = validate_source("version: 999\n", path);
+        assert_eq!(
+            diagnostics[0].location,
+            Some(Location {
+                line: 1,
+                column: 10
+            })
+        );
+    }
+
+    #[test]
+    fn must_validate_included_rules() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let path = root.join("tmexclude.yaml");
+        fs::write(
+            root.join("pack.yaml"),
+            "rules:\n  a: [ missing ]\n  b: []\n",
+        )
+        .unwrap();
+
+        // Included rules can be referenced, and unused ones are not reported.
+        let source = format!(
+            "include: [ pack.yaml ]\ndirectories:\n  - path: {}\n    rules: [ pack/a ]\n",
+            root.display()
+        );
+        let diagnostics = validate_source(&source, &path);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Missing rule: missing");
+        assert_eq!(diagnostics[0].location, None);
+
+        let diagnostics = validate_source("include: [ pack.yaml, missing.yaml ]\n", &path);
+        assert_eq!(
+            diagnostics[0].location,
+            Some(Location {
+                line: 1,
+                column: 23
+            })
+        );
+    }
+}
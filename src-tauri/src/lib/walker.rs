@@ -1,79 +1,49 @@
 //! Utils and actors to walk directories recursively (or not) and perform `TimeMachine` operations on demand.
 
 use std::collections::HashMap;
-use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::SystemTime;
+use std::{fs, iter};
 
 use crossbeam::queue::SegQueue;
 use itertools::Itertools;
 use jwalk::WalkDirGeneric;
 use moka::sync::Cache;
+use serde::Serialize;
 use tap::TapFallible;
-use tauri::async_runtime::Sender;
 use tracing::{debug, warn};
+use ts_rs::TS;
 
-use crate::config::{Directory, Rule, WalkConfig};
-use crate::skip_cache::CachedPath;
-use crate::tmutil::{is_excluded, is_nodump, ExclusionAction, ExclusionActionBatch};
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
-enum ExcludeState {
-    /// The path is currently excluded from backups.
-    Excluded,
-    /// The path is currently excluded from backups.
-    Included,
-    /// The exclude state of the path is unknown (conflict between TimeMachine and NODUMP).
-    Inconsistent,
-}
-
-impl ExcludeState {
-    pub fn is_excluded(&self) -> bool {
-        matches!(self, Self::Excluded)
-    }
-}
-
-fn check_f(support_dump: bool) -> fn(&Path) -> std::io::Result<ExcludeState> {
-    if support_dump {
-        |path| {
-            let excluded = is_excluded(path)?;
-            let nodump = is_nodump(path)?;
-            Ok(match (excluded, nodump) {
-                (true, true) => ExcludeState::Excluded,
-                (false, false) => ExcludeState::Included,
-                _ => ExcludeState::Inconsistent,
-            })
-        }
-    } else {
-        |path| {
-            let excluded = is_excluded(path)?;
-            Ok(if excluded {
-                ExcludeState::Excluded
-            } else {
-                ExcludeState::Included
-            })
-        }
-    }
-}
+use crate::backend::{ExcludeState, ExclusionBackend};
+use crate::config::{CompiledRule, Directory, Protect, RuleId, RuleVerdict, WalkConfig};
+use crate::gitignore::GitignoreCache;
+use crate::overrides::{self, OVERRIDE_FILE};
+use crate::size;
+use crate::skip_cache::{CachedPath, ContentCache};
+use crate::tmutil::{ExclusionAction, ExclusionActionBatch};
 
 /// Walk through a directory with given rules recursively and return an exclusion action plan.
+///
+/// `on_progress` is called with the directory being scanned every once in a while.
 #[allow(clippy::needless_pass_by_value)]
 #[must_use]
 pub fn walk_recursive(
     config: WalkConfig,
-    support_dump: bool,
-    curr_tx: Sender<PathBuf>,
+    backend: Arc<dyn ExclusionBackend>,
+    on_progress: impl Fn(&Path) + Send + Sync + 'static,
     found: Arc<AtomicUsize>,
     abort: Arc<AtomicBool>,
 ) -> ExclusionActionBatch {
-    let check = check_f(support_dump);
-
     let batch_queue = Arc::new(SegQueue::new());
     {
         let batch_queue = batch_queue.clone();
-        let Ok(root) = config.root() else { return ExclusionActionBatch::default() };
+        let Ok(root) = config.root() else {
+            return ExclusionActionBatch::default();
+        };
         let counter = AtomicUsize::new(0);
+        let caches = WalkCaches::default();
         WalkDirGeneric::<(_, ())>::new(root)
             .root_read_dir_state(config)
             .skip_hidden(false)
@@ -101,9 +71,7 @@ pub fn walk_recursive(
                         .expect("f never returns None")
                         == 0
                     {
-                        if let Err(e) = curr_tx.try_send(path.to_path_buf()) {
-                            warn!("Failed to send current path: {}", e);
-                        }
+                        on_progress(path);
                     }
 
                     // Acquire excluded state.
@@ -122,7 +90,7 @@ pub fn walk_recursive(
                                 entry.read_children_path = None;
                                 None
                             } else {
-                                Some((entry, check(&path).ok()?))
+                                Some((entry, backend.state(&path).ok()?))
                             }
                         })
                         .collect_vec();
@@ -134,7 +102,21 @@ pub fn walk_recursive(
                             (PathBuf::from(path.file_name().to_os_string()), *excluded)
                         })
                         .collect();
-                    let diff = generate_diff(path, &shallow_list, &*config.directories);
+
+                    // Layer rules declared in override file onto this subtree.
+                    if shallow_list.contains_key(Path::new(OVERRIDE_FILE))
+                        && config
+                            .directories
+                            .iter()
+                            .any(|directory| path.starts_with(&directory.path))
+                    {
+                        if let Some(directory) = overrides::load(path) {
+                            config.directories.push(directory);
+                        }
+                    }
+
+                    let diff =
+                        generate_diff(path, &shallow_list, &*config.directories, config, &caches);
                     found.fetch_add(diff.count(), Ordering::Relaxed);
 
                     // Exclude already excluded or uncovered children.
@@ -163,16 +145,71 @@ pub fn walk_recursive(
     actions
 }
 
+/// Walk through a subtree with given rules recursively and return an exclusion action plan.
+///
+/// Unlike [`walk_recursive`], the walk starts from `root` instead of the common root of all
+/// directories, and override files in ancestors of `root` are honoured.
+#[must_use]
+pub fn walk_subtree(
+    root: &Path,
+    config: &WalkConfig,
+    backend: Arc<dyn ExclusionBackend>,
+) -> ExclusionActionBatch {
+    if config.skips.iter().any(|skip| root.starts_with(skip)) {
+        // The subtree should be skipped, and walking it may raise permission dialogs on macOS.
+        return ExclusionActionBatch::default();
+    }
+    if has_excluded_ancestor(root, &*backend) {
+        return ExclusionActionBatch::default();
+    }
+
+    // Directories covering `root` are rebased onto it so that the walk starts from `root`.
+    let rebase = |directory: &Directory| {
+        if root.starts_with(&directory.path) {
+            Directory {
+                path: root.to_path_buf(),
+                ..directory.clone()
+            }
+        } else {
+            directory.clone()
+        }
+    };
+    let directories = config
+        .directories
+        .iter()
+        .filter(|directory| root.starts_with(&directory.path) || directory.path.starts_with(root))
+        .map(rebase)
+        .collect_vec();
+    if directories.is_empty() {
+        return ExclusionActionBatch::default();
+    }
+    let overrides = overrides::load_ancestors(root, &config.directories, false);
+    let config = WalkConfig {
+        directories: directories
+            .into_iter()
+            .chain(overrides.iter().map(rebase))
+            .collect(),
+        skips: config.skips.clone(),
+        protect: config.protect.clone(),
+        min_size: config.min_size,
+    };
+    walk_recursive(
+        config,
+        backend,
+        |_| {},
+        Arc::new(AtomicUsize::new(0)),
+        Arc::new(AtomicBool::new(false)),
+    )
+}
+
 /// Walk through a directory with given rules non-recursively and return an exclusion action plan.
 #[must_use]
 pub fn walk_non_recursive(
     root: &Path,
     config: &WalkConfig,
-    support_dump: bool,
+    backend: &dyn ExclusionBackend,
     skip_cache: &Cache<PathBuf, ()>,
 ) -> ExclusionActionBatch {
-    let check = check_f(support_dump);
-
     if skip_cache.get::<CachedPath>(root.into()).is_some() {
         // Skip cache hit, early exit.
         return ExclusionActionBatch::default();
@@ -195,11 +232,7 @@ pub fn walk_non_recursive(
         return ExclusionActionBatch::default();
     }
 
-    if root
-        .ancestors()
-        .any(|path| check(path).map(|s| s.is_excluded()).unwrap_or(false))
-    {
-        // One of its parents is excluded.
+    if has_excluded_ancestor(root, backend) {
         // Note that we don't put this dir into cache because the exclusion state of ancestors is unknown.
         return ExclusionActionBatch::default();
     }
@@ -221,12 +254,19 @@ pub fn walk_non_recursive(
                     } else {
                         Some((
                             PathBuf::from(path.file_name().expect("file name").to_os_string()),
-                            check(&path).ok()?,
+                            backend.state(&path).ok()?,
                         ))
                     }
                 })
                 .collect();
-            generate_diff(root, &shallow_list, directories)
+            let overrides = overrides::load_ancestors(root, &config.directories, true);
+            generate_diff(
+                root,
+                &shallow_list,
+                directories.chain(&overrides),
+                config,
+                &WalkCaches::default(),
+            )
         }
         Err(e) => {
             warn!("Error when scanning dir {:?}: {}", root, e);
@@ -235,36 +275,1005 @@ pub fn walk_non_recursive(
     }
 }
 
-fn generate_diff<'a, 'b>(
+/// Return `true` if `root` or one of its ancestors is excluded, so that there's no need to walk it.
+fn has_excluded_ancestor(root: &Path, backend: &dyn ExclusionBackend) -> bool {
+    root.ancestors().any(|path| {
+        backend
+            .state(path)
+            .map(|s| s.is_excluded())
+            .unwrap_or(false)
+    })
+}
+
+/// Caches shared by all directories visited in a walk.
+#[derive(Clone, Default)]
+struct WalkCaches {
+    contents: ContentCache,
+    gitignores: GitignoreCache,
+}
+
+/// Rules and settings applying to entries of a directory.
+struct Scope<'a> {
+    cwd: &'a Path,
+    rules: Vec<(&'a Path, &'a CompiledRule)>,
+    protects: Vec<&'a Protect>,
+    min_size: Option<u64>,
+    gitignore_min_size: Option<u64>,
+    caches: &'a WalkCaches,
+}
+
+impl<'a> Scope<'a> {
+    fn new(
+        cwd: &'a Path,
+        directories: impl IntoIterator<Item = &'a Directory>,
+        config: &'a WalkConfig,
+        caches: &'a WalkCaches,
+    ) -> Self {
+        let directories = directories
+            .into_iter()
+            .filter(|directory| directory.path.starts_with(cwd) || cwd.starts_with(&directory.path))
+            .collect_vec();
+        let rules = directories
+            .iter()
+            .flat_map(|directory| {
+                directory
+                    .rules
+                    .iter()
+                    .map(|rule| (directory.path.as_path(), rule.as_ref()))
+            })
+            .collect();
+        let protects = iter::once(&*config.protect)
+            .chain(
+                directories
+                    .iter()
+                    .filter(|directory| cwd.starts_with(&directory.path))
+                    .map(|directory| &*directory.protect),
+            )
+            .filter(|protect| !protect.is_empty())
+            .collect();
+        // Use the smallest threshold if multiple directories honour gitignore files here.
+        let gitignore_min_size = directories
+            .iter()
+            .filter(|directory| cwd.starts_with(&directory.path))
+            .filter_map(|directory| directory.from_gitignore)
+            .map(|from_gitignore| from_gitignore.min_size)
+            .min();
+        Self {
+            cwd,
+            rules,
+            protects,
+            min_size: config.min_size,
+            gitignore_min_size,
+            caches,
+        }
+    }
+    fn is_protected(&self, name: &Path) -> bool {
+        self.protects
+            .iter()
+            .any(|protect| protect.is_protected(self.cwd, name))
+    }
+    fn is_ignored_dir(&self, name: &Path) -> bool {
+        let Some(min_size) = self.gitignore_min_size else {
+            return false;
+        };
+        let path = self.cwd.join(name);
+        self.caches.gitignores.is_ignored(&path, true)
+            && path
+                .symlink_metadata()
+                .map(|metadata| metadata.is_dir())
+                .unwrap_or(false)
+            && size::is_at_least(&path, min_size)
+    }
+    /// Verdict of `rule` on entry `name`, taking age and size thresholds into account.
+    ///
+    /// Also returns when the entry is going to cross an age threshold, if it will.
+    fn verdict<V>(
+        &self,
+        rule: &CompiledRule,
+        name: &Path,
+        shallow_list: &HashMap<PathBuf, V>,
+    ) -> (RuleVerdict, Option<SystemTime>) {
+        let mut verdict = rule.verdict(self.cwd, name, shallow_list, &self.caches.contents);
+        let mut recheck_at = None;
+        if verdict == RuleVerdict::Matched {
+            (verdict, recheck_at) = rule.age_verdict(self.cwd, name, SystemTime::now());
+        }
+        let verdict = match rule.min_size().or(self.min_size) {
+            Some(min_size)
+                if verdict == RuleVerdict::Matched
+                    && !size::is_at_least(&self.cwd.join(name), min_size) =>
+            {
+                RuleVerdict::TooSmall
+            }
+            _ => verdict,
+        };
+        (verdict, recheck_at)
+    }
+    /// The rule excluding entry `name`, if any, and when the entry should be re-evaluated because
+    /// it's going to cross an age threshold.
+    fn excluded_by<V>(
+        &self,
+        name: &Path,
+        shallow_list: &HashMap<PathBuf, V>,
+    ) -> (Option<RuleId>, Option<SystemTime>) {
+        // Protected paths take precedence over rules.
+        if self.is_protected(name) {
+            return (None, None);
+        }
+        let mut recheck_at = None;
+        let rule = self
+            .rules
+            .iter()
+            .find_map(|(_, rule)| {
+                let (verdict, at) = self.verdict(rule, name, shallow_list);
+                recheck_at = recheck_at.into_iter().chain(at).min();
+                (verdict == RuleVerdict::Matched).then(|| rule.id().clone())
+            })
+            .or_else(|| self.is_ignored_dir(name).then(RuleId::from_gitignore));
+        (rule, recheck_at)
+    }
+}
+
+fn generate_diff<'a>(
     cwd: &'a Path,
     shallow_list: &'a HashMap<PathBuf, ExcludeState>,
-    directories: impl IntoIterator<Item = &'b Directory>,
+    directories: impl IntoIterator<Item = &'a Directory>,
+    config: &'a WalkConfig,
+    caches: &'a WalkCaches,
 ) -> ExclusionActionBatch {
-    let candidate_rules: Vec<&Rule> = directories
-        .into_iter()
-        .filter(|directory| directory.path.starts_with(cwd) || cwd.starts_with(&directory.path))
-        .flat_map(|directory| &directory.rules)
-        .collect();
-    shallow_list
+    let scope = Scope::new(cwd, directories, config, caches);
+    let mut recheck = HashMap::new();
+    let mut batch: ExclusionActionBatch = shallow_list
         .iter()
         .filter_map(|(name, excluded)| {
-            let expected_excluded = candidate_rules.iter().any(|rule| {
-                rule.excludes.contains(name)
-                    && (rule.if_exists.is_empty()
-                        || rule
-                            .if_exists
-                            .iter()
-                            .any(|if_exist| shallow_list.contains_key(if_exist.as_path())))
-            });
-            match (expected_excluded, *excluded) {
-                (true, ExcludeState::Included | ExcludeState::Inconsistent) => {
-                    Some(ExclusionAction::Add(cwd.join(name)))
+            let (rule, recheck_at) = scope.excluded_by(name, shallow_list);
+            if let Some(at) = recheck_at {
+                recheck.insert(cwd.join(name), at);
+            }
+            match (rule, *excluded) {
+                (Some(rule), ExcludeState::Included | ExcludeState::Inconsistent) => {
+                    Some(ExclusionAction::Add(cwd.join(name), Some(rule)))
                 }
-                (false, ExcludeState::Excluded | ExcludeState::Inconsistent) => {
+                (None, ExcludeState::Excluded | ExcludeState::Inconsistent) => {
                     Some(ExclusionAction::Remove(cwd.join(name)))
                 }
                 _ => None,
             }
         })
-        .into()
+        .into();
+    batch.recheck = recheck;
+    batch
+}
+
+/// Verdict of a rule on a path.
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+pub struct RuleExplanation {
+    /// The rule.
+    pub rule: RuleId,
+    /// The directory the rule is bound to.
+    pub directory: PathBuf,
+    /// Whether the rule matches, and why not if it doesn't.
+    pub verdict: RuleVerdict,
+}
+
+/// Why a path is or isn't excluded.
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export, export_to = "../src/bindings/")]
+#[serde(rename_all = "kebab-case")]
+pub struct Explanation {
+    /// The explained path.
+    pub path: PathBuf,
+    /// Current exclusion state of the path, if it can be queried.
+    pub state: Option<ExcludeState>,
+    /// Directories (and override files) covering the path.
+    pub directories: Vec<PathBuf>,
+    /// The entry in `skips` the path is skipped by, if any.
+    pub skipped_by: Option<PathBuf>,
+    /// The nearest excluded ancestor, if any. Descendants of excluded paths are never visited.
+    pub excluded_ancestor: Option<PathBuf>,
+    /// Whether the path is protected from being excluded.
+    pub protected: bool,
+    /// Whether the path is a large directory ignored by git.
+    pub gitignored: bool,
+    /// Verdicts of all rules applying to the path.
+    pub rules: Vec<RuleExplanation>,
+    /// The rule the path should be excluded by, if any.
+    pub excluded_by: Option<RuleId>,
+}
+
+/// Explain why a path is or isn't excluded with given rules.
+///
+/// `path` must be canonicalized.
+#[must_use]
+pub fn explain(path: &Path, config: &WalkConfig, backend: &dyn ExclusionBackend) -> Explanation {
+    let state = backend.state(path).ok();
+    let mut explanation = Explanation {
+        path: path.to_path_buf(),
+        state,
+        directories: vec![],
+        skipped_by: None,
+        excluded_ancestor: None,
+        protected: false,
+        gitignored: false,
+        rules: vec![],
+        excluded_by: None,
+    };
+    let (Some(cwd), Some(name)) = (path.parent(), path.file_name()) else {
+        return explanation;
+    };
+    let name = Path::new(name);
+
+    let overrides = overrides::load_ancestors(cwd, &config.directories, true);
+    let directories = config.directories.iter().chain(&overrides).collect_vec();
+    explanation.directories = directories
+        .iter()
+        .filter(|directory| path.starts_with(&directory.path))
+        .map(|directory| directory.path.clone())
+        .collect();
+    explanation.skipped_by = config
+        .skips
+        .iter()
+        .find(|skip| path.starts_with(skip))
+        .cloned();
+    explanation.excluded_ancestor = cwd
+        .ancestors()
+        .find(|ancestor| {
+            backend
+                .state(ancestor)
+                .map(|s| s.is_excluded())
+                .unwrap_or(false)
+        })
+        .map(Path::to_path_buf);
+    if explanation.directories.is_empty() {
+        return explanation;
+    }
+
+    let shallow_list: HashMap<_, _> = fs::read_dir(cwd)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| (PathBuf::from(entry.file_name()), ()))
+        .collect();
+    let caches = WalkCaches::default();
+    let scope = Scope::new(cwd, directories, config, &caches);
+    explanation.protected = scope.is_protected(name);
+    explanation.gitignored = scope.is_ignored_dir(name);
+    explanation.rules = scope
+        .rules
+        .iter()
+        .map(|(directory, rule)| RuleExplanation {
+            rule: rule.id().clone(),
+            directory: directory.to_path_buf(),
+            verdict: scope.verdict(rule, name, &shallow_list).0,
+        })
+        .collect();
+    explanation.excluded_by = scope.excluded_by(name, &shallow_list).0;
+    explanation
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use maplit::hashset;
+    use tempfile::TempDir;
+
+    use crate::backend::{ExcludeState, Memory};
+    use crate::config::{
+        CompiledRule, Config, Directory, FileContains, FromGitignore, PreConfig, Protect, Rule,
+        RuleId, RuleVerdict, WalkConfig,
+    };
+    use crate::overrides::OVERRIDE_FILE;
+    use crate::skip_cache::SkipCache;
+    use crate::tmutil::{ExclusionAction, ExclusionActionBatch};
+    use crate::walker::{explain, walk_non_recursive, walk_recursive, walk_subtree};
+
+    fn touch(path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, []).unwrap();
+    }
+
+    fn mkdir(path: impl AsRef<Path>) {
+        fs::create_dir_all(path).unwrap();
+    }
+
+    fn sorted(mut batch: ExclusionActionBatch) -> ExclusionActionBatch {
+        batch.add.sort();
+        batch.remove.sort();
+        batch
+    }
+
+    /// Build a mock tree and a walk config covering it.
+    ///
+    /// ```text
+    /// root
+    /// ├── a            Cargo.toml + target (included)           -> add target
+    /// │   └── target
+    /// │       └── nested   Cargo.toml + target (ignored, parent excluded)
+    /// ├── b            target (excluded, no Cargo.toml)         -> remove target
+    /// ├── c            Cargo.toml + target (inconsistent)       -> add target
+    /// ├── d            node_modules (inconsistent, no rule)     -> remove node_modules
+    /// ├── e            Cargo.toml + target (excluded)           -> nothing
+    /// └── skipped      Cargo.toml + target (included, skipped)  -> nothing
+    /// ```
+    fn mock_tree() -> (TempDir, PathBuf, WalkConfig, Memory) {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+
+        touch(root.join("a/Cargo.toml"));
+        touch(root.join("a/target/nested/Cargo.toml"));
+        mkdir(root.join("a/target/nested/target"));
+        mkdir(root.join("b/target"));
+        touch(root.join("c/Cargo.toml"));
+        mkdir(root.join("c/target"));
+        mkdir(root.join("d/node_modules"));
+        touch(root.join("e/Cargo.toml"));
+        mkdir(root.join("e/target"));
+        touch(root.join("skipped/Cargo.toml"));
+        mkdir(root.join("skipped/target"));
+
+        let backend = Memory::default();
+        backend.seed(root.join("b/target"), ExcludeState::Excluded);
+        backend.seed(root.join("c/target"), ExcludeState::Inconsistent);
+        backend.seed(root.join("d/node_modules"), ExcludeState::Inconsistent);
+        backend.seed(root.join("e/target"), ExcludeState::Excluded);
+
+        let config = WalkConfig {
+            directories: vec![Directory {
+                path: root.clone(),
+                rules: vec![Arc::new(
+                    CompiledRule::new(
+                        "cargo",
+                        Rule {
+                            excludes: vec![PathBuf::from("target")],
+                            if_exists: vec![PathBuf::from("Cargo.toml")],
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap(),
+                )],
+                from_gitignore: None,
+                protect: Arc::default(),
+            }],
+            skips: hashset![root.join("skipped")],
+            protect: Arc::default(),
+            min_size: None,
+        };
+        (temp_dir, root, config, backend)
+    }
+
+    #[test]
+    fn must_walk_recursive() {
+        let (_temp_dir, root, config, backend) = mock_tree();
+        let found = Arc::new(AtomicUsize::new(0));
+
+        let batch = walk_recursive(
+            config,
+            Arc::new(backend),
+            |_| {},
+            found.clone(),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        let batch = sorted(batch);
+        assert_eq!(batch.add, [root.join("a/target"), root.join("c/target")]);
+        assert_eq!(
+            batch.remove,
+            [root.join("b/target"), root.join("d/node_modules")]
+        );
+        assert_eq!(found.load(Ordering::Relaxed), 4);
+        assert_eq!(
+            batch
+                .rules
+                .get(&root.join("a/target"))
+                .map(|rule| &*rule.name),
+            Some("cargo")
+        );
+    }
+
+    #[test]
+    fn must_walk_non_recursive() {
+        let (_temp_dir, root, config, backend) = mock_tree();
+        let cache = SkipCache::default();
+
+        let batch = walk_non_recursive(&root.join("a"), &config, &backend, &cache);
+        assert_eq!(batch.add, [root.join("a/target")]);
+        assert!(batch.remove.is_empty());
+
+        let batch = walk_non_recursive(&root.join("c"), &config, &backend, &cache);
+        assert_eq!(batch.add, [root.join("c/target")]);
+        assert!(batch.remove.is_empty());
+
+        let batch = walk_non_recursive(&root.join("d"), &config, &backend, &cache);
+        assert!(batch.add.is_empty());
+        assert_eq!(batch.remove, [root.join("d/node_modules")]);
+
+        // Skipped directories.
+        let batch = walk_non_recursive(&root.join("skipped"), &config, &backend, &cache);
+        assert!(batch.is_empty());
+
+        // One of the ancestors is excluded.
+        let batch = walk_non_recursive(&root.join("e/target"), &config, &backend, &cache);
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn must_apply_batch() {
+        let (_temp_dir, root, config, backend) = mock_tree();
+        let cache = SkipCache::default();
+
+        let batch = walk_non_recursive(&root.join("c"), &config, &backend, &cache)
+            + walk_non_recursive(&root.join("d"), &config, &backend, &cache)
+            + ExclusionActionBatch {
+                add: vec![root.join("missing")],
+                remove: vec![],
+                rules: HashMap::new(),
+                recheck: HashMap::new(),
+            };
+        let errors = batch
+            .apply(&backend)
+            .expect_err("must fail on missing path");
+        assert_eq!(
+            errors.into_keys().collect::<Vec<_>>(),
+            [root.join("missing")]
+        );
+
+        assert_eq!(
+            backend.history(),
+            [
+                ExclusionAction::Add(root.join("c/target"), None),
+                ExclusionAction::Remove(root.join("d/node_modules")),
+            ]
+        );
+        assert_eq!(
+            backend.states().get(&root.join("c/target")),
+            Some(&ExcludeState::Excluded)
+        );
+        assert_eq!(
+            backend.states().get(&root.join("d/node_modules")),
+            Some(&ExcludeState::Included)
+        );
+
+        // Nothing left to do.
+        assert!(walk_non_recursive(&root.join("c"), &config, &backend, &cache).is_empty());
+        assert!(walk_non_recursive(&root.join("d"), &config, &backend, &cache).is_empty());
+    }
+
+    #[test]
+    fn must_match_globs() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+
+        touch(root.join("App.xcodeproj/project.pbxproj"));
+        mkdir(root.join("build"));
+        mkdir(root.join("foo.egg-info"));
+        mkdir(root.join("cmake-build-debug"));
+        mkdir(root.join("cmake-build-release"));
+        mkdir(root.join("cmake"));
+
+        let rule = |name, excludes: &[&str], if_exists: &[&str]| {
+            Arc::new(
+                CompiledRule::new(
+                    name,
+                    Rule {
+                        excludes: excludes.iter().map(PathBuf::from).collect(),
+                        if_exists: if_exists.iter().map(PathBuf::from).collect(),
+                        ..Default::default()
+                    },
+                )
+                .unwrap(),
+            )
+        };
+        let config = WalkConfig {
+            directories: vec![Directory {
+                path: root.clone(),
+                rules: vec![
+                    rule("xcode", &["build"], &["*.xcodeproj"]),
+                    rule("python", &["*.egg-info"], &[]),
+                    rule("cmake", &["cmake-build-*"], &["CMakeLists.txt"]),
+                ],
+                from_gitignore: None,
+                protect: Arc::default(),
+            }],
+            skips: hashset![],
+            protect: Arc::default(),
+            min_size: None,
+        };
+
+        let batch = sorted(walk_non_recursive(
+            &root,
+            &config,
+            &Memory::default(),
+            &SkipCache::default(),
+        ));
+        // cmake-build-* is not excluded because CMakeLists.txt is missing.
+        assert_eq!(batch.add, [root.join("build"), root.join("foo.egg-info")]);
+        assert!(batch.remove.is_empty());
+    }
+
+    #[test]
+    fn must_match_nested_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+
+        touch(root.join("flutter/pubspec.yaml"));
+        mkdir(root.join("flutter/ios/Pods"));
+        mkdir(root.join("flutter/android/.gradle"));
+        mkdir(root.join("flutter/android/app/build"));
+        mkdir(root.join("native/ios/Pods"));
+
+        let backend = Arc::new(Memory::default());
+        backend.seed(root.join("native/ios/Pods"), ExcludeState::Excluded);
+
+        let config = WalkConfig {
+            directories: vec![Directory {
+                path: root.clone(),
+                rules: vec![Arc::new(
+                    CompiledRule::new(
+                        "flutter",
+                        Rule {
+                            excludes: vec![
+                                PathBuf::from("ios/Pods"),
+                                PathBuf::from("android/.gradle"),
+                                PathBuf::from("android/app/build"),
+                            ],
+                            if_exists: vec![PathBuf::from("pubspec.yaml")],
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap(),
+                )],
+                from_gitignore: None,
+                protect: Arc::default(),
+            }],
+            skips: hashset![],
+            protect: Arc::default(),
+            min_size: None,
+        };
+
+        let batch = sorted(walk_recursive(
+            config.clone(),
+            backend.clone(),
+            |_| {},
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        ));
+        assert_eq!(
+            batch.add,
+            [
+                root.join("flutter/android/.gradle"),
+                root.join("flutter/android/app/build"),
+                root.join("flutter/ios/Pods"),
+            ]
+        );
+        // Not a flutter project.
+        assert_eq!(batch.remove, [root.join("native/ios/Pods")]);
+
+        let batch = walk_non_recursive(
+            &root.join("flutter/android/app"),
+            &config,
+            &*backend,
+            &SkipCache::default(),
+        );
+        assert_eq!(batch.add, [root.join("flutter/android/app/build")]);
+    }
+
+    #[test]
+    fn must_match_conditions() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+
+        touch(root.join("locked/composer.json"));
+        touch(root.join("locked/composer.lock"));
+        mkdir(root.join("locked/vendor"));
+        touch(root.join("unlocked/composer.json"));
+        mkdir(root.join("unlocked/vendor"));
+        mkdir(root.join("plain/build"));
+        touch(root.join("kept/.keep-build"));
+        mkdir(root.join("kept/build"));
+
+        let config = WalkConfig {
+            directories: vec![Directory {
+                path: root.clone(),
+                rules: vec![
+                    Arc::new(
+                        CompiledRule::new(
+                            "composer",
+                            Rule {
+                                excludes: vec![PathBuf::from("vendor")],
+                                if_all_exist: vec![
+                                    PathBuf::from("composer.json"),
+                                    PathBuf::from("composer.lock"),
+                                ],
+                                ..Default::default()
+                            },
+                        )
+                        .unwrap(),
+                    ),
+                    Arc::new(
+                        CompiledRule::new(
+                            "build",
+                            Rule {
+                                excludes: vec![PathBuf::from("build")],
+                                if_not_exists: vec![PathBuf::from(".keep-build")],
+                                ..Default::default()
+                            },
+                        )
+                        .unwrap(),
+                    ),
+                ],
+                from_gitignore: None,
+                protect: Arc::default(),
+            }],
+            skips: hashset![],
+            protect: Arc::default(),
+            min_size: None,
+        };
+
+        let batch = sorted(walk_recursive(
+            config,
+            Arc::new(Memory::default()),
+            |_| {},
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        ));
+        assert_eq!(
+            batch.add,
+            [root.join("locked/vendor"), root.join("plain/build")]
+        );
+        assert!(batch.remove.is_empty());
+    }
+
+    #[test]
+    fn must_match_file_contents() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+
+        fs::create_dir_all(root.join("workspace/target")).unwrap();
+        fs::write(
+            root.join("workspace/Cargo.toml"),
+            "[workspace]\nmembers = [\"a\"]\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("package/target")).unwrap();
+        fs::write(root.join("package/Cargo.toml"), "[package]\nname = \"a\"\n").unwrap();
+        mkdir(root.join("missing/target"));
+
+        let config = WalkConfig {
+            directories: vec![Directory {
+                path: root.clone(),
+                rules: vec![Arc::new(
+                    CompiledRule::new(
+                        "cargo workspace",
+                        Rule {
+                            excludes: vec![PathBuf::from("target")],
+                            if_file_contains: Some(FileContains {
+                                file: PathBuf::from("Cargo.toml"),
+                                pattern: r"(?m)^\[workspace\]".to_string(),
+                            }),
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap(),
+                )],
+                from_gitignore: None,
+                protect: Arc::default(),
+            }],
+            skips: hashset![],
+            protect: Arc::default(),
+            min_size: None,
+        };
+
+        let batch = walk_recursive(
+            config,
+            Arc::new(Memory::default()),
+            |_| {},
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        );
+        assert_eq!(batch.add, [root.join("workspace/target")]);
+        assert!(batch.remove.is_empty());
+    }
+
+    #[test]
+    fn must_honour_gitignore() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+
+        mkdir(root.join("repo/.git"));
+        fs::write(
+            root.join("repo/.gitignore"),
+            "node_modules/\ncoverage/\n*.log\n",
+        )
+        .unwrap();
+        mkdir(root.join("repo/node_modules/a"));
+        fs::write(root.join("repo/node_modules/a/index.js"), [0; 64]).unwrap();
+        mkdir(root.join("repo/coverage"));
+        fs::write(root.join("repo/coverage/lcov.info"), [0; 8]).unwrap();
+        fs::write(root.join("repo/debug.log"), [0; 64]).unwrap();
+        mkdir(root.join("repo/pkg/dist"));
+        fs::write(root.join("repo/pkg/.gitignore"), "dist/\n").unwrap();
+        fs::write(root.join("repo/pkg/dist/bundle.js"), [0; 64]).unwrap();
+        mkdir(root.join("not-a-repo/node_modules"));
+        fs::write(root.join("not-a-repo/.gitignore"), "node_modules/\n").unwrap();
+        fs::write(root.join("not-a-repo/node_modules/index.js"), [0; 64]).unwrap();
+
+        let config = WalkConfig {
+            directories: vec![Directory {
+                path: root.clone(),
+                rules: vec![],
+                from_gitignore: Some(FromGitignore { min_size: 32 }),
+                protect: Arc::default(),
+            }],
+            skips: hashset![],
+            protect: Arc::default(),
+            min_size: None,
+        };
+
+        let batch = sorted(walk_recursive(
+            config,
+            Arc::new(Memory::default()),
+            |_| {},
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        ));
+        // coverage is too small, debug.log is not a directory.
+        assert_eq!(
+            batch.add,
+            [root.join("repo/node_modules"), root.join("repo/pkg/dist")]
+        );
+        assert_eq!(
+            batch.rules.get(&root.join("repo/node_modules")),
+            Some(&RuleId::from_gitignore())
+        );
+        assert!(batch.remove.is_empty());
+    }
+
+    #[test]
+    fn must_honour_overrides() {
+        let (_temp_dir, root, config, backend) = mock_tree();
+        let backend = Arc::new(backend);
+
+        touch(root.join("f/Cargo.toml"));
+        mkdir(root.join("f/target"));
+        mkdir(root.join("f/out"));
+        touch(root.join("f/sub/Cargo.toml"));
+        mkdir(root.join("f/sub/target"));
+        mkdir(root.join("f/sub/out"));
+        fs::write(
+            root.join("f").join(OVERRIDE_FILE),
+            "excludes: [ out ]\nincludes: [ target ]\n",
+        )
+        .unwrap();
+
+        let batch = sorted(walk_recursive(
+            config.clone(),
+            backend.clone(),
+            |_| {},
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        ));
+        assert_eq!(
+            batch.add,
+            [
+                root.join("a/target"),
+                root.join("c/target"),
+                root.join("f/out"),
+                root.join("f/sub/out")
+            ]
+        );
+
+        // Overrides in ancestors apply to non-recursive walks too.
+        let batch = walk_non_recursive(
+            &root.join("f/sub"),
+            &config,
+            &*backend,
+            &SkipCache::default(),
+        );
+        assert_eq!(batch.add, [root.join("f/sub/out")]);
+        assert!(batch.remove.is_empty());
+
+        let batch = sorted(walk_subtree(&root.join("f"), &config, backend));
+        assert_eq!(batch.add, [root.join("f/out"), root.join("f/sub/out")]);
+        assert!(batch.remove.is_empty());
+    }
+
+    #[test]
+    fn must_not_walk_skipped_subtree() {
+        let (_temp_dir, root, config, backend) = mock_tree();
+        let backend = Arc::new(backend);
+
+        touch(root.join("skipped/sub/Cargo.toml"));
+        mkdir(root.join("skipped/sub/target"));
+        touch(root.join("e/target/sub/Cargo.toml"));
+        mkdir(root.join("e/target/sub/target"));
+
+        // Skipped subtrees.
+        assert!(walk_subtree(&root.join("skipped"), &config, backend.clone()).is_empty());
+        assert!(walk_subtree(&root.join("skipped/sub"), &config, backend.clone()).is_empty());
+
+        // One of the ancestors is excluded.
+        assert!(walk_subtree(&root.join("e/target/sub"), &config, backend).is_empty());
+    }
+
+    #[test]
+    fn must_respect_protect() {
+        let (_temp_dir, root, mut config, backend) = mock_tree();
+        config.protect = Arc::new(Protect::new("", vec![root.join("e/target")]).unwrap());
+        config.directories[0].protect =
+            Arc::new(Protect::new("", vec![PathBuf::from("a/target")]).unwrap());
+
+        let batch = sorted(walk_recursive(
+            config,
+            Arc::new(backend),
+            |_| {},
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        ));
+        // a/target is protected, so the walk goes on into it.
+        assert_eq!(
+            batch.add,
+            [root.join("a/target/nested/target"), root.join("c/target")]
+        );
+        assert_eq!(
+            batch.remove,
+            [
+                root.join("b/target"),
+                root.join("d/node_modules"),
+                root.join("e/target")
+            ]
+        );
+    }
+
+    #[test]
+    fn must_respect_min_size() {
+        let (_temp_dir, root, mut config, backend) = mock_tree();
+        fs::write(root.join("c/target/blob"), [0; 64]).unwrap();
+        config.min_size = Some(64);
+        let backend = Arc::new(backend);
+
+        let batch = sorted(walk_recursive(
+            config.clone(),
+            backend.clone(),
+            |_| {},
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        ));
+        assert_eq!(batch.add, [root.join("c/target")]);
+        assert_eq!(
+            batch.remove,
+            [
+                root.join("b/target"),
+                root.join("d/node_modules"),
+                root.join("e/target")
+            ]
+        );
+        let explanation = explain(&root.join("e/target"), &config, &*backend);
+        assert_eq!(explanation.rules[0].verdict, RuleVerdict::TooSmall);
+
+        // Subtree rescans agree with full scans.
+        let batch = walk_subtree(&root.join("a"), &config, backend.clone());
+        assert!(batch.add.is_empty());
+        let batch = walk_subtree(&root.join("c"), &config, backend.clone());
+        assert_eq!(batch.add, [root.join("c/target")]);
+
+        // Thresholds on rules take precedence over the global one.
+        config.directories[0].rules = vec![Arc::new(
+            CompiledRule::new(
+                "cargo",
+                Rule {
+                    excludes: vec![PathBuf::from("target")],
+                    if_exists: vec![PathBuf::from("Cargo.toml")],
+                    min_size: Some(0),
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        )];
+        let batch = sorted(walk_recursive(
+            config,
+            backend,
+            |_| {},
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        ));
+        assert_eq!(batch.add, [root.join("a/target"), root.join("c/target")]);
+    }
+
+    #[test]
+    fn must_attribute_identical_rules_deterministically() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        touch(root.join("Cargo.toml"));
+        mkdir(root.join("target"));
+
+        let attributed_rule = |rules: &str| {
+            let rule = "{ excludes: [ target ], if-exists: [ Cargo.toml ] }";
+            let pre_config: PreConfig = serde_yaml::from_str(&format!(
+                "directories: [ {{ path: {}, rules: {rules} }} ]\n\
+                 rules: {{ cargo: {rule}, rust: {rule}, all: [ rust, cargo ] }}\n",
+                root.display()
+            ))
+            .unwrap();
+            let config = Config::try_from(pre_config).unwrap();
+            let batch = walk_non_recursive(
+                &root,
+                &config.walk,
+                &Memory::default(),
+                &SkipCache::default(),
+            );
+            batch.rules[&root.join("target")].to_string()
+        };
+        // Identical rules are kept apart, and hash order never decides between them.
+        for _ in 0..8 {
+            // Rules are tried in the order directories list them.
+            assert_eq!(attributed_rule("[ rust, cargo ]"), "rust");
+            assert_eq!(attributed_rule("[ cargo, rust ]"), "cargo");
+            // Rules reached through a union are tried in the order of their ids.
+            assert_eq!(attributed_rule("[ all ]"), "all > cargo");
+        }
+    }
+
+    #[test]
+    fn must_report_recheck_times() {
+        let (_temp_dir, root, mut config, backend) = mock_tree();
+        config.directories[0].rules = vec![Arc::new(
+            CompiledRule::new(
+                "cargo",
+                Rule {
+                    excludes: vec![PathBuf::from("target")],
+                    if_exists: vec![PathBuf::from("Cargo.toml")],
+                    older_than: Some("1d".parse().unwrap()),
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        )];
+
+        let batch = walk_non_recursive(&root.join("a"), &config, &backend, &SkipCache::default());
+        assert!(batch.add.is_empty());
+        let at = batch.recheck[&root.join("a/target")];
+        assert!(at > SystemTime::now() + Duration::from_secs(23 * 60 * 60));
+    }
+
+    #[test]
+    fn must_explain() {
+        let (_temp_dir, root, mut config, backend) = mock_tree();
+        mkdir(root.join("e/target/sub"));
+        config.protect = Arc::new(Protect::new("", vec![root.join("c/target")]).unwrap());
+
+        let explanation = explain(&root.join("a/target"), &config, &backend);
+        assert_eq!(explanation.state, Some(ExcludeState::Included));
+        assert_eq!(explanation.directories, vec![root.clone()]);
+        assert_eq!(explanation.rules.len(), 1);
+        assert_eq!(explanation.rules[0].rule.name, "cargo");
+        assert_eq!(explanation.rules[0].verdict, RuleVerdict::Matched);
+        assert_eq!(
+            explanation.excluded_by.map(|rule| rule.name),
+            Some(String::from("cargo"))
+        );
+
+        let explanation = explain(&root.join("b/target"), &config, &backend);
+        assert_eq!(explanation.rules[0].verdict, RuleVerdict::MissingSentinel);
+        assert!(explanation.excluded_by.is_none());
+
+        let explanation = explain(&root.join("c/target"), &config, &backend);
+        assert_eq!(explanation.rules[0].verdict, RuleVerdict::Matched);
+        assert!(explanation.protected);
+        assert!(explanation.excluded_by.is_none());
+
+        let explanation = explain(&root.join("e/target/sub"), &config, &backend);
+        assert_eq!(explanation.excluded_ancestor, Some(root.join("e/target")));
+        assert_eq!(explanation.rules[0].verdict, RuleVerdict::NotMatched);
+
+        let explanation = explain(&root.join("skipped/target"), &config, &backend);
+        assert_eq!(explanation.skipped_by, Some(root.join("skipped")));
+    }
 }
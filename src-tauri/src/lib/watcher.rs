@@ -1,28 +1,65 @@
 //! Filesystem watcher.
 
-use std::io;
+#[cfg(not(target_os = "macos"))]
+use std::collections::HashSet;
+use std::path::Path;
+#[cfg(not(target_os = "macos"))]
+use std::path::PathBuf;
+#[cfg(target_os = "macos")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(target_os = "macos"))]
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
 use std::sync::Weak;
-use std::time::Duration;
+#[cfg(not(target_os = "macos"))]
+use std::time::Instant;
+use std::time::{Duration, SystemTime};
+use std::{io, mem};
 
-use fsevent_stream::ffi::{kFSEventStreamCreateFlagIgnoreSelf, kFSEventStreamEventIdSinceNow};
+#[cfg(target_os = "macos")]
+use fsevent_stream::ffi::{
+    kFSEventStreamCreateFlagFileEvents, kFSEventStreamCreateFlagIgnoreSelf,
+    kFSEventStreamEventIdSinceNow,
+};
+#[cfg(target_os = "macos")]
 use fsevent_stream::stream::{create_event_stream, EventStreamHandler};
+#[cfg(not(target_os = "macos"))]
+use futures::channel::oneshot;
+#[cfg(target_os = "macos")]
 use futures::StreamExt;
-use tracing::{debug, error};
+#[cfg(not(target_os = "macos"))]
+use notify::event::{EventKind, ModifyKind};
+#[cfg(not(target_os = "macos"))]
+use notify::{RecursiveMode, Watcher};
+use tracing::{debug, error, info};
 
+use crate::age::Schedule;
+use crate::config::Config;
+use crate::metrics::Metrics;
 use crate::mission::Mission;
+use crate::overrides::OverrideTracker;
+use crate::size;
 use crate::skip_cache::SkipCache;
-use crate::walker::walk_non_recursive;
+use crate::walker::{walk_non_recursive, walk_subtree};
 
 const EVENT_DELAY: Duration = Duration::from_secs(30);
+/// Events on the config file are coalesced within this window, so that editors saving by writing
+/// a temporary file and renaming it over the config trigger only one reload.
+const CONFIG_EVENT_DELAY: Duration = Duration::from_secs(1);
+/// How often to look for paths crossing an age threshold.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(60);
 
+#[cfg(target_os = "macos")]
 struct DropGuard(Option<EventStreamHandler>);
 
+#[cfg(target_os = "macos")]
 impl DropGuard {
     pub const fn new(handler: EventStreamHandler) -> Self {
         Self(Some(handler))
     }
 }
 
+#[cfg(target_os = "macos")]
 impl Drop for DropGuard {
     fn drop(&mut self) {
         if let Some(mut handler) = self.0.take() {
@@ -31,6 +68,19 @@ impl Drop for DropGuard {
     }
 }
 
+/// Sets the flag when dropped, to stop the recheck loop along with the watch task.
+#[cfg(target_os = "macos")]
+struct StopGuard(Arc<AtomicBool>);
+
+#[cfg(target_os = "macos")]
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Watch task bound to a mission.
+///
 /// # Errors
 /// Returns `io::Error` if fs event stream creation fails.
 pub async fn watch_task(mission: Weak<Mission>) -> io::Result<()> {
@@ -40,16 +90,162 @@ pub async fn watch_task(mission: Weak<Mission>) -> io::Result<()> {
             "mission is dropped before watch task is started",
         )
     })?;
-    let config = mission.config_();
-    let metrics = mission.metrics();
+    watch(mission.config_(), mission.metrics()).await
+}
+
+/// Watch the config file of a mission, and reload it when it's edited on disk.
+///
+/// The directory containing the config file is watched instead of the file itself, because
+/// editors may replace the file on save. The task stops once the mission is dropped.
+///
+/// # Errors
+/// Returns `io::Error` if fs event stream creation fails.
+#[cfg(target_os = "macos")]
+pub async fn watch_config_task(mission: Weak<Mission>) -> io::Result<()> {
+    let path = mission
+        .upgrade()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "mission is dropped before config watch task is started",
+            )
+        })?
+        .config_path()
+        .to_path_buf();
+    // FSEvents reports canonical paths.
+    let dir = path.parent().unwrap_or(&path).canonicalize()?;
+    let path = dir.join(path.file_name().unwrap_or_default());
+
+    let (mut stream, event_handle) = create_event_stream(
+        [dir.as_path()],
+        kFSEventStreamEventIdSinceNow,
+        CONFIG_EVENT_DELAY,
+        kFSEventStreamCreateFlagFileEvents | kFSEventStreamCreateFlagIgnoreSelf,
+    )?;
+    let _guard = DropGuard::new(event_handle);
+
+    while let Some(items) = stream.next().await {
+        if !items.iter().any(|item| item.path == path) {
+            continue;
+        }
+        let Some(mission) = mission.upgrade() else {
+            break;
+        };
+        if !path.exists() {
+            // The file is being replaced, wait for it to appear again.
+            continue;
+        }
+        info!("Config file changed, reloading");
+        let r = tokio::task::spawn_blocking(move || mission.reload_config_file()).await;
+        match r {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Failed to reload config, keeping current one: {}", e),
+            Err(e) => error!("Config reload task failed: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Watch the config file of a mission, and reload it when it's edited on disk.
+///
+/// The directory containing the config file is watched instead of the file itself, because
+/// editors may replace the file on save. The task stops once the mission is dropped.
+///
+/// # Errors
+/// Returns `io::Error` if the filesystem watcher can't be created.
+#[cfg(not(target_os = "macos"))]
+pub async fn watch_config_task(mission: Weak<Mission>) -> io::Result<()> {
+    let path = mission
+        .upgrade()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "mission is dropped before config watch task is started",
+            )
+        })?
+        .config_path()
+        .to_path_buf();
+    let dir = path.parent().unwrap_or(&path).canonicalize()?;
+    let path = dir.join(path.file_name().unwrap_or_default());
+
+    let (tx, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(notify_error)?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .map_err(notify_error)?;
+
+    let (done, done_rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _watcher = watcher;
+        watch_config_loop(&events, &done, &path, &mission);
+        done.send(()).ok();
+    });
+    done_rx.await.ok();
+    Ok(())
+}
+
+/// Reload the config of `mission` on events touching `path`, until `done` is canceled or the
+/// mission is dropped.
+#[cfg(not(target_os = "macos"))]
+fn watch_config_loop(
+    events: &Receiver<notify::Result<notify::Event>>,
+    done: &oneshot::Sender<()>,
+    path: &Path,
+    mission: &Weak<Mission>,
+) {
+    // Reads never change the config, and reloading reads it.
+    let touches = |event: notify::Result<notify::Event>| match event {
+        Ok(event) => {
+            !matches!(event.kind, EventKind::Access(_)) && event.paths.iter().any(|p| p == path)
+        }
+        Err(e) => {
+            error!("Filesystem watcher error: {}", e);
+            false
+        }
+    };
+
+    while !done.is_canceled() {
+        let mut changed = false;
+        match events.recv_timeout(RECHECK_INTERVAL) {
+            Ok(event) => {
+                changed = touches(event);
+                let deadline = Instant::now() + CONFIG_EVENT_DELAY;
+                while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+                    match events.recv_timeout(timeout) {
+                        Ok(event) => changed |= touches(event),
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        let Some(mission) = mission.upgrade() else {
+            return;
+        };
+        // The file may be being replaced, wait for it to appear again.
+        if changed && path.exists() {
+            info!("Config file changed, reloading");
+            if let Err(e) = mission.reload_config_file() {
+                error!("Failed to reload config, keeping current one: {}", e);
+            }
+        }
+    }
+}
 
+/// Watch directories in given config and apply changes on the fly.
+///
+/// # Errors
+/// Returns `io::Error` if fs event stream creation fails.
+#[cfg(target_os = "macos")]
+pub async fn watch(config: Arc<Config>, metrics: Arc<Metrics>) -> io::Result<()> {
     let paths = config
         .walk
         .directories
         .iter()
         .map(|directory| directory.path.as_path());
-    let no_include = config.no_include;
-    let support_dump = config.support_dump;
 
     let (mut stream, event_handle) = create_event_stream(
         paths,
@@ -60,39 +256,266 @@ pub async fn watch_task(mission: Weak<Mission>) -> io::Result<()> {
     let _guard = DropGuard::new(event_handle);
 
     let cache = SkipCache::default();
-    while let Some(items) = stream.next().await {
-        for item in items {
-            if !item.path.as_os_str().is_empty() {
-                tauri::async_runtime::spawn_blocking({
-                    let _path = item.path.clone();
-                    let walk_config = config.walk.clone();
-                    let cache = cache.clone();
-                    let metrics = metrics.clone();
-                    move || {
-                        let mut batch =
-                            walk_non_recursive(&item.path, &walk_config, support_dump, &cache);
-                        if batch.is_empty() {
-                            return;
-                        }
-                        debug!("Apply batch {:?}", batch);
-                        if no_include {
-                            batch.remove.clear();
-                        }
-                        metrics.inc_excluded(batch.add.len());
-                        metrics.inc_included(batch.remove.len());
-                        if let Some(last_file) = batch.add.last() {
-                            metrics.set_last_excluded(last_file.as_path());
-                        }
-                        if let Err(errors) = batch.apply(support_dump) {
-                            for (path, e) in errors {
-                                error!("Error when applying on file {}: {}", path.display(), e);
-                            }
-                        }
+    let overrides = OverrideTracker::default();
+    let schedule = Arc::new(Schedule::default());
+
+    // Paths crossing an age threshold are re-evaluated without waiting for fs events.
+    let stop = Arc::new(AtomicBool::new(false));
+    let _stop_guard = StopGuard(stop.clone());
+    std::thread::spawn({
+        let config = config.clone();
+        let cache = cache.clone();
+        let overrides = overrides.clone();
+        let metrics = metrics.clone();
+        let schedule = schedule.clone();
+        move || {
+            while !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(RECHECK_INTERVAL);
+                for path in schedule.take_due(SystemTime::now()) {
+                    if let Some(parent) = path.parent() {
+                        debug!("{:?} crossed an age threshold, rescan {:?}", path, parent);
+                        handle_event(parent, &config, &cache, &overrides, &schedule, &metrics);
                     }
-                });
+                }
             }
         }
+    });
+
+    while let Some(items) = stream.next().await {
+        // Events are handled off the executor, so that the CLI doesn't need an async runtime.
+        std::thread::spawn({
+            let config = config.clone();
+            let cache = cache.clone();
+            let overrides = overrides.clone();
+            let metrics = metrics.clone();
+            let schedule = schedule.clone();
+            move || {
+                for item in items {
+                    if !item.path.as_os_str().is_empty() {
+                        handle_event(&item.path, &config, &cache, &overrides, &schedule, &metrics);
+                    }
+                }
+            }
+        });
     }
 
     Ok(())
 }
+
+/// Watch directories in given config and apply changes on the fly.
+///
+/// Events are handled on a dedicated thread, which stops once the returned future is dropped.
+///
+/// # Errors
+/// Returns `io::Error` if the filesystem watcher can't be created.
+#[cfg(not(target_os = "macos"))]
+pub async fn watch(config: Arc<Config>, metrics: Arc<Metrics>) -> io::Result<()> {
+    let (tx, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(notify_error)?;
+    for directory in &config.walk.directories {
+        watcher
+            .watch(&directory.path, RecursiveMode::Recursive)
+            .map_err(notify_error)?;
+    }
+
+    let (done, done_rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _watcher = watcher;
+        watch_loop(&events, &done, &config, &metrics);
+        done.send(()).ok();
+    });
+    done_rx.await.ok();
+    Ok(())
+}
+
+/// Handle events from `events` until `done` is canceled or the watcher is dropped.
+///
+/// Directories changed within `EVENT_DELAY` of each other are coalesced into one rescan, like
+/// FSEvents does on macOS.
+#[cfg(not(target_os = "macos"))]
+fn watch_loop(
+    events: &Receiver<notify::Result<notify::Event>>,
+    done: &oneshot::Sender<()>,
+    config: &Config,
+    metrics: &Metrics,
+) {
+    let cache = SkipCache::default();
+    let overrides = OverrideTracker::default();
+    let schedule = Schedule::default();
+    let collect = |changed: &mut HashSet<PathBuf>, event: notify::Result<notify::Event>| {
+        match event {
+            // Reads and metadata changes (including our own marks) never change a verdict.
+            Ok(event)
+                if matches!(
+                    event.kind,
+                    EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_))
+                ) => {}
+            Ok(event) => changed.extend(
+                event
+                    .paths
+                    .iter()
+                    .filter_map(|path| path.parent().map(Path::to_path_buf)),
+            ),
+            Err(e) => error!("Filesystem watcher error: {}", e),
+        }
+    };
+
+    while !done.is_canceled() {
+        let mut changed = HashSet::new();
+        match events.recv_timeout(RECHECK_INTERVAL) {
+            Ok(event) => {
+                collect(&mut changed, event);
+                let deadline = Instant::now() + EVENT_DELAY;
+                while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+                    match events.recv_timeout(timeout) {
+                        Ok(event) => collect(&mut changed, event),
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        // Paths crossing an age threshold are re-evaluated without waiting for fs events.
+        for path in schedule.take_due(SystemTime::now()) {
+            if let Some(parent) = path.parent() {
+                debug!("{:?} crossed an age threshold, rescan {:?}", path, parent);
+                changed.insert(parent.to_path_buf());
+            }
+        }
+        if done.is_canceled() {
+            return;
+        }
+        for path in changed {
+            handle_event(&path, config, &cache, &overrides, &schedule, metrics);
+        }
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+fn notify_error(e: notify::Error) -> io::Error {
+    match e.kind {
+        notify::ErrorKind::Io(e) => e,
+        _ => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+/// Rescan a changed directory and apply the resulting batch.
+///
+/// The whole subtree is rescanned if the override file in the directory has changed. Paths going
+/// to cross an age threshold are added to `schedule`. Cached sizes of the directory and its
+/// ancestors are dropped, because the change may have pushed them across a size threshold.
+pub(crate) fn handle_event(
+    path: &Path,
+    config: &Config,
+    cache: &SkipCache,
+    overrides: &OverrideTracker,
+    schedule: &Schedule,
+    metrics: &Metrics,
+) {
+    size::invalidate(path);
+    let mut batch = if overrides.changed(path) {
+        debug!("Override file changed in {:?}, rescan subtree", path);
+        walk_subtree(path, &config.walk, config.backend.clone())
+    } else {
+        walk_non_recursive(path, &config.walk, &*config.backend, cache)
+    };
+    schedule.extend(mem::take(&mut batch.recheck));
+    if batch.is_empty() {
+        return;
+    }
+    debug!("Apply batch {:?}", batch);
+    if config.no_include {
+        batch.remove.clear();
+    }
+    for (path, rule) in &batch.rules {
+        debug!("Exclude {:?} by rule {}", path, rule);
+    }
+    metrics.inc_excluded(batch.add.len());
+    metrics.inc_included(batch.remove.len());
+    if let Some(last_file) = batch.add.last() {
+        metrics.set_last_excluded(last_file.as_path(), batch.rules.get(last_file));
+    }
+    if let Err(errors) = batch.apply(&*config.backend) {
+        for (path, e) in errors {
+            error!("Error when applying on file {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use crate::age::Schedule;
+    use crate::backend::{ExcludeState, ExclusionBackend, Memory};
+    use crate::config::{CompiledRule, Config, Directory, Rule, WalkConfig};
+    use crate::metrics::Metrics;
+    use crate::overrides::OverrideTracker;
+    use crate::skip_cache::SkipCache;
+    use crate::watcher::handle_event;
+
+    #[test]
+    fn must_exclude_directory_grown_past_min_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        fs::write(root.join("Cargo.toml"), []).unwrap();
+        fs::create_dir(root.join("target")).unwrap();
+        fs::write(root.join("target/a"), [0; 32]).unwrap();
+
+        let backend = Arc::new(Memory::default());
+        let config = Config {
+            no_include: false,
+            backend: backend.clone(),
+            walk: Arc::new(WalkConfig {
+                directories: vec![Directory {
+                    path: root.clone(),
+                    rules: vec![Arc::new(
+                        CompiledRule::new(
+                            "cargo",
+                            Rule {
+                                excludes: vec![PathBuf::from("target")],
+                                if_exists: vec![PathBuf::from("Cargo.toml")],
+                                ..Default::default()
+                            },
+                        )
+                        .unwrap(),
+                    )],
+                    from_gitignore: None,
+                    protect: Arc::default(),
+                }],
+                skips: Default::default(),
+                protect: Arc::default(),
+                min_size: Some(64),
+            }),
+        };
+        let cache = SkipCache::default();
+        let overrides = OverrideTracker::default();
+        let schedule = Schedule::default();
+        let metrics = Metrics::default();
+        let handle = |path: PathBuf| {
+            handle_event(&path, &config, &cache, &overrides, &schedule, &metrics);
+        };
+
+        handle(root.clone());
+        assert_eq!(
+            backend.state(&root.join("target")).unwrap(),
+            ExcludeState::Included
+        );
+
+        // Writing into `target` fires an event on it, and the rescan of `root` that follows sees
+        // the new size instead of the cached one.
+        fs::write(root.join("target/b"), [0; 32]).unwrap();
+        handle(root.join("target"));
+        handle(root.clone());
+        assert_eq!(
+            backend.state(&root.join("target")).unwrap(),
+            ExcludeState::Excluded
+        );
+    }
+}
@@ -7,14 +7,17 @@
 #[macro_use]
 extern crate objc;
 
+use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use once_cell::sync::Lazy;
 use regex::Regex;
 use tap::TapFallible;
+use tauri::api::dialog;
 use tauri::{
-    ActivationPolicy, CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu,
-    SystemTrayMenuItem,
+    ActivationPolicy, AppHandle, CustomMenuItem, Manager, SystemTray, SystemTrayEvent,
+    SystemTrayMenu, SystemTrayMenuItem, Window,
 };
 use tracing::{error, instrument};
 use tracing_subscriber::layer::SubscriberExt;
@@ -22,8 +25,8 @@ use tracing_subscriber::util::SubscriberInitExt;
 use window_vibrancy::NSVisualEffectMaterial;
 
 use tmexclude_lib::{
-    ApplyErrors, ConfigManager, ExclusionActionBatch, Metrics, Mission, PreConfig, ScanStatus,
-    Store,
+    ApplyErrors, ConfigError, ConfigManager, Diagnostic, ExclusionActionBatch, Explanation,
+    Metrics, Mission, PreConfig, ScanStatus, Store,
 };
 
 use crate::decorations::WindowExt;
@@ -48,9 +51,21 @@ fn get_config(mission: tauri::State<Arc<Mission>>) -> Arc<PreConfig> {
 
 #[tauri::command]
 #[instrument(skip_all)]
-fn set_config(mission: tauri::State<Arc<Mission>>, config: PreConfig) -> Result<(), String> {
+fn set_config(
+    mission: tauri::State<Arc<Mission>>,
+    config: PreConfig,
+) -> Result<(), Vec<Diagnostic>> {
     let mission = mission.inner().clone();
-    mission.set_config(config).map_err(|e| e.to_string())
+    mission.set_config(config).map_err(|e| match e {
+        ConfigError::Invalid(diagnostics) => diagnostics,
+        e => vec![Diagnostic::error(&e)],
+    })
+}
+
+#[tauri::command]
+#[instrument(skip_all)]
+fn validate_config(mission: tauri::State<Arc<Mission>>, mut config: PreConfig) -> Vec<Diagnostic> {
+    mission.validate_config(&mut config)
 }
 
 #[tauri::command]
@@ -77,10 +92,10 @@ async fn apply_action_batch(
     mission: tauri::State<'_, Arc<Mission>>,
     batch: ExclusionActionBatch,
 ) -> Result<(), ApplyErrors> {
-    let support_dump = mission.inner().config().support_dump;
+    let backend = mission.backend();
     tauri::async_runtime::spawn_blocking(move || {
         let r = batch
-            .apply(support_dump)
+            .apply(&*backend)
             .tap_err(|e| e.values().for_each(|e| error!(?e, "Apply batch failed")));
         ApplyErrors::from(r)
     })
@@ -88,6 +103,12 @@ async fn apply_action_batch(
     .expect("spawn_blocking failed")
 }
 
+#[tauri::command]
+#[instrument(skip(mission))]
+fn explain(mission: tauri::State<Arc<Mission>>, path: PathBuf) -> Result<Explanation, String> {
+    mission.explain(&path).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 #[instrument(skip(mission))]
 fn store_get(mission: tauri::State<Arc<Mission>>, key: &str) -> Option<serde_json::Value> {
@@ -118,6 +139,23 @@ fn system_tray() -> SystemTray {
     SystemTray::new().with_menu(tray_menu)
 }
 
+/// Report that the mission can't be created, e.g. the config is written by a newer version, and
+/// quit once the user has seen it.
+fn report_startup_error(app: AppHandle, path: &Path, e: &ConfigError) {
+    let mut message = format!("Failed to load config {}", path.display());
+    let mut source: Option<&dyn Error> = Some(e);
+    while let Some(e) = source {
+        message.push_str(&format!(": {e}"));
+        source = e.source();
+    }
+    error!("{}", message);
+    // Blocking dialogs must not run on the main thread.
+    std::thread::spawn(move || {
+        dialog::blocking::message(None::<&Window>, "TimeMachine Exclude", message);
+        app.exit(1);
+    });
+}
+
 fn main() {
     static PATH_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#""/.*""#).unwrap());
     let _guard = sentry::init((
@@ -176,10 +214,12 @@ fn main() {
             metrics,
             get_config,
             set_config,
+            validate_config,
             scan_status,
             start_full_scan,
             stop_full_scan,
             apply_action_batch,
+            explain,
             build_meta,
             store_get,
             store_set,
@@ -187,10 +227,20 @@ fn main() {
         ])
         .setup(move |app| {
             let store = Store::new(&app.path_resolver().app_config_dir().unwrap());
-            app.manage(
+            let config_path = config_manager.path().to_path_buf();
+            // Tasks of the mission run on the tauri runtime.
+            let mission = tauri::async_runtime::block_on(async {
                 Mission::new_arc(app.handle(), config_manager, store)
-                    .expect("failed to create mission"),
-            );
+            });
+            match mission {
+                Ok(mission) => {
+                    app.manage(mission);
+                }
+                Err(e) => {
+                    report_startup_error(app.handle(), &config_path, &e);
+                    return Ok(());
+                }
+            };
             let main_window = app.get_window("main").unwrap();
             window_vibrancy::apply_vibrancy(
                 &main_window,
@@ -0,0 +1,51 @@
+use std::fs;
+
+use tempfile::TempDir;
+
+#[test]
+fn must_report_located_problems() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.yaml");
+    fs::write(
+        &config_path,
+        format!(
+            "backends: [ cachedir-tag ]\ndirectories:\n  - path: {}\n    rules: [ missing ]\n",
+            temp_dir.path().display()
+        ),
+    )
+    .unwrap();
+
+    let output = assert_cmd::Command::cargo_bin("tmexclude-cli")
+        .unwrap()
+        .arg("-c")
+        .arg(&config_path)
+        .arg("check-config")
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!(
+            "{}:4:14: error: Missing rule: missing\n",
+            config_path.display()
+        )
+    );
+}
+
+#[test]
+fn must_accept_default() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.yaml");
+    fs::write(&config_path, include_str!("../../config.example.yaml")).unwrap();
+
+    assert_cmd::Command::cargo_bin("tmexclude-cli")
+        .unwrap()
+        .env("HOME", temp_dir.path())
+        .arg("-c")
+        .arg(&config_path)
+        .arg("check-config")
+        .assert()
+        .success();
+}
@@ -1,78 +1,109 @@
-// use std::fs;
-//
-// use tempfile::TempDir;
-
-// #[test]
-// fn must_create_default_when_not_initialized() {
-//     let mock_home = TempDir::new().unwrap();
-//     let config_path = mock_home.path().join(".config/tmexclude.yaml");
-//
-//     assert_cmd::Command::cargo_bin("tmexclude")
-//         .unwrap()
-//         .env("HOME", mock_home.path())
-//         .arg("read-config")
-//         .assert()
-//         .success();
-//
-//     assert_eq!(
-//         fs::read_to_string(config_path).unwrap(),
-//         include_str!("../../config.example.yaml")
-//     );
-// }
-//
-// #[test]
-// fn must_not_overwrite_default_if_exists() {
-//     let mock_home = TempDir::new().unwrap();
-//     let config_path = mock_home.path().join(".config/tmexclude.yaml");
-//
-//     fs::create_dir_all(config_path.parent().unwrap()).unwrap();
-//     let modified_config = include_str!("../../config.example.yaml").replace("Library", "Library2");
-//     fs::write(&config_path, &modified_config).unwrap();
-//
-//     assert_cmd::Command::cargo_bin("tmexclude")
-//         .unwrap()
-//         .env("HOME", mock_home.path())
-//         .arg("read-config")
-//         .assert()
-//         .success();
-//
-//     assert_eq!(fs::read_to_string(config_path).unwrap(), modified_config);
-// }
-//
-// #[test]
-// fn must_not_create_if_given_exists() {
-//     let mock_home = TempDir::new().unwrap();
-//     let default_config_path = mock_home.path().join(".config/tmexclude.yaml");
-//     let config_path = mock_home.path().join("config.yaml");
-//
-//     fs::write(&config_path, include_str!("../../config.example.yaml")).unwrap();
-//
-//     assert_cmd::Command::cargo_bin("tmexclude")
-//         .unwrap()
-//         .env("HOME", mock_home.path())
-//         .arg("-c")
-//         .arg(config_path)
-//         .arg("read-config")
-//         .assert()
-//         .success();
-//
-//     assert!(!default_config_path.exists());
-// }
-//
-// #[test]
-// fn must_not_create_if_given_not_exists() {
-//     let mock_home = TempDir::new().unwrap();
-//     let default_config_path = mock_home.path().join(".config/tmexclude.yaml");
-//     let config_path = mock_home.path().join("config.yaml");
-//
-//     assert_cmd::Command::cargo_bin("tmexclude")
-//         .unwrap()
-//         .env("HOME", mock_home.path())
-//         .arg("-c")
-//         .arg(config_path)
-//         .arg("read-config")
-//         .assert()
-//         .failure();
-//
-//     assert!(!default_config_path.exists());
-// }
+use std::fs;
+use std::time::Duration;
+
+use tempfile::TempDir;
+
+#[test]
+fn must_create_default_when_not_initialized() {
+    let mock_home = TempDir::new().unwrap();
+    let config_path = mock_home.path().join(".config/tmexclude.yaml");
+
+    assert_cmd::Command::cargo_bin("tmexclude-cli")
+        .unwrap()
+        .env("HOME", mock_home.path())
+        .arg("read-config")
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(config_path).unwrap(),
+        include_str!("../../config.example.yaml")
+    );
+}
+
+#[test]
+fn must_not_overwrite_default_if_exists() {
+    let mock_home = TempDir::new().unwrap();
+    let config_path = mock_home.path().join(".config/tmexclude.yaml");
+
+    fs::create_dir_all(config_path.parent().unwrap()).unwrap();
+    let modified_config = include_str!("../../config.example.yaml").replace("Library", "Library2");
+    fs::write(&config_path, &modified_config).unwrap();
+
+    assert_cmd::Command::cargo_bin("tmexclude-cli")
+        .unwrap()
+        .env("HOME", mock_home.path())
+        .arg("read-config")
+        .assert()
+        .success();
+
+    assert_eq!(fs::read_to_string(config_path).unwrap(), modified_config);
+}
+
+#[test]
+fn must_not_create_if_given_exists() {
+    let mock_home = TempDir::new().unwrap();
+    let default_config_path = mock_home.path().join(".config/tmexclude.yaml");
+    let config_path = mock_home.path().join("config.yaml");
+
+    fs::write(&config_path, include_str!("../../config.example.yaml")).unwrap();
+
+    assert_cmd::Command::cargo_bin("tmexclude-cli")
+        .unwrap()
+        .env("HOME", mock_home.path())
+        .arg("-c")
+        .arg(config_path)
+        .arg("read-config")
+        .assert()
+        .success();
+
+    assert!(!default_config_path.exists());
+}
+
+#[test]
+fn must_not_create_if_given_not_exists() {
+    let mock_home = TempDir::new().unwrap();
+    let default_config_path = mock_home.path().join(".config/tmexclude.yaml");
+    let config_path = mock_home.path().join("config.yaml");
+
+    assert_cmd::Command::cargo_bin("tmexclude-cli")
+        .unwrap()
+        .env("HOME", mock_home.path())
+        .arg("-c")
+        .arg(config_path)
+        .arg("read-config")
+        .assert()
+        .failure();
+
+    assert!(!default_config_path.exists());
+}
+
+#[test]
+fn must_report_config_from_newer_version() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.yaml");
+    fs::write(&config_path, "version: 999\n").unwrap();
+
+    for command in ["read-config", "watch"] {
+        let output = assert_cmd::Command::cargo_bin("tmexclude-cli")
+            .unwrap()
+            .env("HOME", temp_dir.path())
+            .arg("-c")
+            .arg(&config_path)
+            .arg(command)
+            .timeout(Duration::from_secs(10))
+            .assert()
+            .code(1)
+            .get_output()
+            .stderr
+            .clone();
+        let stderr = String::from_utf8(output).unwrap();
+        assert!(stderr.contains(&format!("Failed to load config {}", config_path.display())));
+        assert!(stderr.contains("Config version 999 is not supported"));
+        assert!(!stderr.contains("panicked"));
+    }
+
+    // The config is neither reset nor backed up.
+    assert_eq!(fs::read_to_string(&config_path).unwrap(), "version: 999\n");
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+}
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Whose modification time age conditions of a rule are evaluated against.
+ */
+export type AgeOf = "candidate" | "sentinel";
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface ApplyErrors { errors: Record<string, string>, }
\ No newline at end of file
+export type ApplyErrors = { errors: { [key in string]?: string }, };
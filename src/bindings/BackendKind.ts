@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Kinds of available backends.
+ */
+export type BackendKind = "time-machine" | "nodump" | "cachedir-tag";
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface BuildMeta { version: string, timestamp: string, }
\ No newline at end of file
+export type BuildMeta = { version: string, timestamp: string, };
@@ -0,0 +1,20 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Location } from "./Location";
+import type { Severity } from "./Severity";
+
+/**
+ * A problem found in a config.
+ */
+export type Diagnostic = { 
+/**
+ * How serious the problem is.
+ */
+severity: Severity, 
+/**
+ * Description of the problem.
+ */
+message: string, 
+/**
+ * Where the problem is in the config file, if known.
+ */
+location: Location | null, };
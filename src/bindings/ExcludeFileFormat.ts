@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Format of an exclude file.
+ */
+export type ExcludeFileFormat = "restic" | "borg" | "rsync" | "tar";
@@ -1,3 +1,19 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RuleId } from "./RuleId";
 
-export interface ExclusionActionBatch { add: Array<string>, remove: Array<string>, }
\ No newline at end of file
+/**
+ * Represents a batch of tmutil modifications.
+ */
+export type ExclusionActionBatch = { 
+/**
+ * Paths to be added to backup exclusion list.
+ */
+add: Array<string>, 
+/**
+ * Paths to be removed from backup exclusion list.
+ */
+remove: Array<string>, 
+/**
+ * Rules causing paths in `add` to be excluded, if known.
+ */
+rules: { [key in string]?: RuleId }, };
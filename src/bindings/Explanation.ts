@@ -0,0 +1,45 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ExcludeState } from "./ExcludeState";
+import type { RuleExplanation } from "./RuleExplanation";
+import type { RuleId } from "./RuleId";
+
+/**
+ * Why a path is or isn't excluded.
+ */
+export type Explanation = { 
+/**
+ * The explained path.
+ */
+path: string, 
+/**
+ * Current exclusion state of the path, if it can be queried.
+ */
+state: ExcludeState | null, 
+/**
+ * Directories (and override files) covering the path.
+ */
+directories: Array<string>, 
+/**
+ * The entry in `skips` the path is skipped by, if any.
+ */
+"skipped-by": string | null, 
+/**
+ * The nearest excluded ancestor, if any. Descendants of excluded paths are never visited.
+ */
+"excluded-ancestor": string | null, 
+/**
+ * Whether the path is protected from being excluded.
+ */
+protected: boolean, 
+/**
+ * Whether the path is a large directory ignored by git.
+ */
+gitignored: boolean, 
+/**
+ * Verdicts of all rules applying to the path.
+ */
+rules: Array<RuleExplanation>, 
+/**
+ * The rule the path should be excluded by, if any.
+ */
+"excluded-by": RuleId | null, };
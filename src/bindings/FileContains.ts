@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A file in the same directory as the path to be excluded, and a regex its content must match.
+ */
+export type FileContains = { 
+/**
+ * Path to the file, relative to the directory.
+ */
+file: string, 
+/**
+ * Regex to search in the file.
+ */
+pattern: string, };
@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A position in a YAML document.
+ */
+export type Location = { 
+/**
+ * Line number, starting from 1.
+ */
+line: number, 
+/**
+ * Column number, starting from 1.
+ */
+column: number, };
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface Metrics { "files-excluded": number, "files-included": number, "last-excluded": string, "last-excluded-time": number, }
\ No newline at end of file
+export type Metrics = { "files-excluded": number, "files-included": number, "last-excluded": string, "last-excluded-rule": string, "last-excluded-time": number, };
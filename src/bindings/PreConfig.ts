@@ -1,5 +1,33 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BackendKind } from "./BackendKind";
 import type { PreDirectory } from "./PreDirectory";
+import type { PreExcludeFile } from "./PreExcludeFile";
 import type { PreRule } from "./PreRule";
 
-export interface PreConfig { "no-include": boolean, "support-dump": boolean, directories: Array<PreDirectory>, skips: Array<string>, rules: Record<string, PreRule>, }
\ No newline at end of file
+export type PreConfig = { 
+/**
+ * Version of the config schema. See [`CURRENT_VERSION`].
+ */
+version: number, 
+/**
+ * Rule packs to include, relative to the config file. Their rules are namespaced by the
+ * pack, e.g. `rules/cargo`.
+ */
+include: Array<string>, "no-include": boolean, "support-dump": boolean, 
+/**
+ * Backends to apply exclusions with. Platform defaults are used if unset, and they are never
+ * written back, so that a config file can be shared between platforms.
+ */
+backends?: Array<BackendKind>, "exclude-files": Array<PreExcludeFile>, directories: Array<PreDirectory>, skips: Array<string>, 
+/**
+ * Paths or patterns never to be excluded, even if some rules match.
+ */
+protect: Array<string>, 
+/**
+ * Paths smaller than this (in bytes) are not excluded, unless rules say otherwise.
+ */
+"min-size": number | null, 
+/**
+ * Rules are written sorted by name, so that saved configs are stable.
+ */
+rules: { [key in string]?: PreRule }, };
@@ -1,3 +1,19 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface PreDirectory { path: string, rules: Array<string>, }
\ No newline at end of file
+export type PreDirectory = { path: string, rules: Array<string>, 
+/**
+ * Exclude directories ignored by `.gitignore` files, layered with rules.
+ */
+"from-gitignore": boolean, 
+/**
+ * Only ignored directories at least this large (in bytes) are excluded. Defaults to 1 MiB.
+ */
+"gitignore-min-size": number | null, 
+/**
+ * Paths or patterns never to be excluded in this directory, even if some rules match.
+ */
+protect: Array<string>, 
+/**
+ * Apply rules with any of these tags too.
+ */
+tags: Array<string>, };
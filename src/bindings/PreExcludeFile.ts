@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ExcludeFileFormat } from "./ExcludeFileFormat";
+
+/**
+ * An exclude file to be generated for other backup tools.
+ */
+export type PreExcludeFile = { path: string, format: ExcludeFileFormat, };
@@ -1,4 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { Rule } from "./Rule";
 
-export type PreRule = Rule | Array<string>;
\ No newline at end of file
+export type PreRule = Rule | Array<string>;
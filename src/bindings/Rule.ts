@@ -1,3 +1,67 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AgeOf } from "./AgeOf";
+import type { FileContains } from "./FileContains";
 
-export interface Rule { excludes: Array<string>, "if-exists": Array<string>, }
\ No newline at end of file
+/**
+ * Rules to be applied on a specific set of directories.
+ */
+export type Rule = { 
+/**
+ * Paths to be excluded. Globs like `*.egg-info` and nested paths like `android/app/build` are
+ * allowed.
+ */
+excludes: Array<string>, 
+/**
+ * Exclude paths if *any* of these paths exist in the same directory as the path to be excluded.
+ * Globs like `*.xcodeproj` are allowed.
+ */
+"if-exists": Array<string>, 
+/**
+ * Regexes matching names of paths to be excluded, for cases globs can't express.
+ */
+"excludes-regex": Array<string>, 
+/**
+ * Exclude paths if any path matching *any* of these regexes exists in the same directory.
+ */
+"if-exists-regex": Array<string>, 
+/**
+ * Exclude paths only if *none* of these paths exist in the same directory.
+ */
+"if-not-exists": Array<string>, 
+/**
+ * Exclude paths only if *all* of these paths exist in the same directory.
+ */
+"if-all-exist": Array<string>, 
+/**
+ * Exclude paths only if a file in the same directory contains given pattern.
+ */
+"if-file-contains": FileContains | null, 
+/**
+ * Exclude paths only if their total size is at least this many bytes. Defaults to the global
+ * `min-size`.
+ */
+"min-size": number | null, 
+/**
+ * Exclude paths only if they haven't been modified for this long, e.g. `30d`.
+ */
+"older-than": string | null, 
+/**
+ * Exclude paths only if they have been modified within this long, e.g. `1d`.
+ */
+"newer-than": string | null, 
+/**
+ * Whose modification time `older-than` and `newer-than` are evaluated against.
+ */
+"age-of": AgeOf, 
+/**
+ * Disabled rules are validated but never applied. Defaults to `true`.
+ */
+enabled: boolean, 
+/**
+ * Human readable description of the rule.
+ */
+description: string | null, 
+/**
+ * Tags of the rule. Directories may reference rules by tag.
+ */
+tags: Array<string>, };
@@ -0,0 +1,20 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { RuleId } from "./RuleId";
+import type { RuleVerdict } from "./RuleVerdict";
+
+/**
+ * Verdict of a rule on a path.
+ */
+export type RuleExplanation = { 
+/**
+ * The rule.
+ */
+rule: RuleId, 
+/**
+ * The directory the rule is bound to.
+ */
+directory: string, 
+/**
+ * Whether the rule matches, and why not if it doesn't.
+ */
+verdict: RuleVerdict, };
@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Identifies a rule by its name, and the union rules it's reached through.
+ */
+export type RuleId = { 
+/**
+ * Name of the concrete rule.
+ */
+name: string, 
+/**
+ * Names of union rules leading to the concrete rule, outermost first.
+ */
+ancestry: Array<string>, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Why a rule does or doesn't match an entry.
+ */
+export type RuleVerdict = "matched" | "not-matched" | "missing-sentinel" | "forbidden-sentinel" | "content-mismatch" | "too-small" | "too-new" | "too-old";
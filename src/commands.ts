@@ -2,6 +2,7 @@ import {PreConfig} from "./bindings/PreConfig";
 import {ScanStatus} from "./bindings/ScanStatus";
 import {ExclusionActionBatch} from "./bindings/ExclusionActionBatch";
 import {InvokeArgs} from "@tauri-apps/api/tauri";
+import {Diagnostic} from "./bindings/Diagnostic";
 
 const invoke = async <T>(cmd: string, args?: InvokeArgs) => {
   if (typeof window === "undefined") {
@@ -39,6 +40,10 @@ export const setConfig = async (config: PreConfig) => {
   return await invoke<void>("set_config", {config});
 }
 
+export const validateConfig = async (config: PreConfig) => {
+  return await invoke<Diagnostic[]>("validate_config", {config}) ?? [];
+}
+
 export const scanStatus = async () => {
   return await invoke<ScanStatus>("scan_status") ?? {step: "idle"} as ScanStatus;
 }
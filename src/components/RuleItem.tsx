@@ -1,4 +1,15 @@
-import {Accordion, ActionIcon, Group, Menu, MultiSelect, SegmentedControl, Stack, Text, TextInput} from "@mantine/core";
+import {
+  Accordion,
+  ActionIcon,
+  Group,
+  Menu,
+  MultiSelect,
+  SegmentedControl,
+  Stack,
+  Switch,
+  Text,
+  TextInput
+} from "@mantine/core";
 import {IconDots, IconPencil, IconTrash} from "@tabler/icons";
 import {useSetRecoilState} from "recoil";
 import {perRuleState, rulesState} from "../states";
@@ -67,7 +78,19 @@ export const RuleItem = React.memo(({
         } else {
           setValue({
             excludes: [],
-            "if-exists": []
+            "if-exists": [],
+            "excludes-regex": [],
+            "if-exists-regex": [],
+            "if-not-exists": [],
+            "if-all-exist": [],
+            "if-file-contains": null,
+            "min-size": null,
+            "older-than": null,
+            "newer-than": null,
+            "age-of": "candidate",
+            enabled: true,
+            description: null,
+            tags: []
           });
         }
       }
@@ -97,7 +120,8 @@ export const RuleItem = React.memo(({
             finishRename();
             setRenaming(false);
           }}
-        /> : <Text size={"sm"} sx={{cursor: "pointer"}}>{name}</Text>}
+        /> : <Text size={"sm"} sx={{cursor: "pointer"}}
+                   color={!Array.isArray(value) && !value.enabled ? "dimmed" : undefined}>{name}</Text>}
       </Accordion.Control>
       <Menu withinPortal>
         <Menu.Target>
@@ -134,6 +158,17 @@ export const RuleItem = React.memo(({
           }}
           placeholder={t("pick_merge_rules")!}
         />) : (<>
+          <Switch
+            size={"xs"}
+            label={t('rule_enabled')}
+            checked={value.enabled}
+            onChange={(e) => {
+              setValue({
+                ...value,
+                enabled: e.currentTarget.checked
+              });
+            }}
+          />
           <Text size="sm">{t('paths_to_exclude')}</Text>
           <MultiSelect searchable creatable
                        getCreateLabel={(value) => `+ New ${value}`}
@@ -144,8 +179,8 @@ export const RuleItem = React.memo(({
                        value={value.excludes}
                        onChange={(newExcludes) => {
                          setValue({
-                           excludes: newExcludes,
-                           "if-exists": value["if-exists"]
+                           ...value,
+                           excludes: newExcludes
                          });
                        }}
           />
@@ -161,7 +196,7 @@ export const RuleItem = React.memo(({
                        value={value["if-exists"]}
                        onChange={(newIfExists) => {
                          setValue({
-                           excludes: value.excludes,
+                           ...value,
                            "if-exists": newIfExists
                          });
                        }}
@@ -123,7 +123,11 @@ const WatchedDir = () => {
         }
         return [...dirPaths, {
           path: selected,
-          rules: []
+          rules: [],
+          "from-gitignore": false,
+          "gitignore-min-size": null,
+          protect: [],
+          tags: []
         }];
       });
     }
@@ -1,11 +1,12 @@
 import {useRecoilValue, useResetRecoilState} from "recoil";
-import {configChangedState, draftConfigState} from "../../states";
+import {configChangedState, configReloadErrorState, draftConfigState} from "../../states";
 import {Box, Button, Group, Header, Text, Tooltip} from "@mantine/core";
 import {IconAdjustments, IconAlertTriangle} from "@tabler/icons";
 import React, {useState} from "react";
 import {evDrag} from "../../utils";
 import {useTranslation} from "react-i18next";
 import {setConfig} from "../../commands";
+import {Diagnostic} from "../../bindings/Diagnostic";
 
 export const MainHeader = React.forwardRef<HTMLElement>((props, ref) => {
   const {t} = useTranslation();
@@ -13,6 +14,7 @@ export const MainHeader = React.forwardRef<HTMLElement>((props, ref) => {
   const changed = useRecoilValue(configChangedState);
   const resetDraft = useResetRecoilState(draftConfigState);
   const draftConfig = useRecoilValue(draftConfigState);
+  const reloadError = useRecoilValue(configReloadErrorState);
 
   const [error, setError] = useState<string | null>(null);
 
@@ -21,8 +23,11 @@ export const MainHeader = React.forwardRef<HTMLElement>((props, ref) => {
       try {
         await setConfig(draftConfig);
       } catch (_e: any) {
-        const e = _e as string;
-        setError(e);
+        const diagnostics = _e as Diagnostic[];
+        setError(diagnostics
+          .filter(diagnostic => diagnostic.severity === "error")
+          .map(diagnostic => diagnostic.message)
+          .join("\n"));
       }
     }
   };
@@ -51,6 +56,12 @@ export const MainHeader = React.forwardRef<HTMLElement>((props, ref) => {
           <IconAdjustments size={20} strokeWidth={1.5}/>
         </Box>
         <Text size={"md"}>{t('preference')}</Text>
+        {reloadError !== null &&
+          <Tooltip color={"orange"} label={t('config_file_invalid') + "\n" + reloadError} multiline withArrow>
+            <Box sx={{height: 20, width: 20}} pt={2}>
+              <IconAlertTriangle color={"orange"} size={16} strokeWidth={1.5}/>
+            </Box>
+          </Tooltip>}
         {changed && <>
           <Box sx={{flexGrow: 1}}/>
           <Button variant={"subtle"} compact sx={{boxShadow: "none"}}
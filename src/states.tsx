@@ -10,6 +10,7 @@ import {useEffect} from "react";
 import {ApplyErrors} from "./bindings/ApplyErrors";
 import {disableAutoStart, enableAutoStart, getAutoStart, getConfig, getStore, scanStatus, setStore} from "./commands";
 import i18n from "./i18n";
+import {Diagnostic} from "./bindings/Diagnostic";
 
 const finalConfigEffect: AtomEffect<PreConfig | null> = ({
                                                            setSelf,
@@ -36,6 +37,44 @@ export const finalConfigState = atom({
   effects: [finalConfigEffect,]
 })
 
+const configReloadErrorEffect: AtomEffect<string | null> = ({
+                                                              setSelf,
+                                                            }) => {
+  const f = async () => {
+    if (typeof window === "undefined") {
+      return () => {
+      };
+    }
+    const listen = await import("@tauri-apps/api/event").then(tauri => tauri.listen);
+    const unlistenFailed = await listen<Diagnostic[]>("config_reload_failed", ({payload}) => {
+      setSelf(payload
+        .filter(diagnostic => diagnostic.severity === "error")
+        .map(diagnostic => diagnostic.location !== null
+          ? `${diagnostic.location.line}:${diagnostic.location.column}: ${diagnostic.message}`
+          : diagnostic.message)
+        .join("\n"));
+    });
+    const unlistenChanged = await listen<PreConfig>("config_changed", () => {
+      setSelf(null);
+    });
+    return () => {
+      unlistenFailed();
+      unlistenChanged();
+    };
+  }
+  const unlisten = f();
+  return () => {
+    unlisten.then(unlisten => unlisten());
+  }
+};
+
+// Problems of the config file on disk, if it's edited but can't be reloaded.
+export const configReloadErrorState = atom<string | null>({
+  key: 'configReloadError',
+  default: null,
+  effects: [configReloadErrorEffect,]
+})
+
 export const draftConfigState = atom({
   key: 'draftConfig',
   default: finalConfigState,
@@ -223,6 +262,7 @@ export const actionBatchState = equalSelector({
       return {
         add: [],
         remove: [],
+        rules: {},
       };
     }
   },
@@ -233,7 +273,8 @@ export const selectedActionBatchState = atom<ExclusionActionBatch>({
   key: 'selectedActionBatch',
   default: {
     remove: [],
-    add: []
+    add: [],
+    rules: {}
   },
 })
 
@@ -273,6 +314,7 @@ export const SyncActionBatch = () => {
       set({
         add: [...initial.add],
         remove: [],
+        rules: initial.rules,
       });
     }
   }, [initial, set]);